@@ -0,0 +1,43 @@
+use std::fmt;
+
+// Failures surfaced by the fallible APIs. Serialization errors keep only the message so JSON and
+// TOML failures share one variant.
+#[derive(Debug)]
+pub enum PageIndexError {
+    Io(std::io::Error),
+    Serde(String),
+    NotFound(String),
+    Parse(String),
+}
+
+impl fmt::Display for PageIndexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PageIndexError::Io(e) => write!(f, "io error: {}", e),
+            PageIndexError::Serde(msg) => write!(f, "serialization error: {}", msg),
+            PageIndexError::NotFound(node_id) => write!(f, "node '{}' not found", node_id),
+            PageIndexError::Parse(msg) => write!(f, "parse error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for PageIndexError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PageIndexError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for PageIndexError {
+    fn from(e: std::io::Error) -> Self {
+        PageIndexError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for PageIndexError {
+    fn from(e: serde_json::Error) -> Self {
+        PageIndexError::Serde(e.to_string())
+    }
+}
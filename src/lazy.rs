@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::parser::{self, ParseOptions};
+use crate::tree::{DocumentTree, Node};
+
+// A parsed structure that keeps the source instead of per-node body copies. Each node maps to the
+// byte range its body occupies in the source, and node_text builds the body only when asked, so
+// reading a few sections of a huge file never materializes the rest. Every option applies as in
+// the eager parser, so ids, depths and texts match parse_markdown_with_options.
+pub struct LazyDocumentTree {
+    source: Arc<str>,
+    structure: DocumentTree,
+    // (start, end, indent): the body's byte range and the leading columns to strip from its lines
+    body_spans: HashMap<String, (usize, usize, usize)>,
+    options: ParseOptions,
+}
+
+impl LazyDocumentTree {
+    pub fn parse(doc_id: &str, source: impl Into<Arc<str>>) -> Self {
+        Self::parse_with_options(doc_id, source, &ParseOptions::default())
+    }
+
+    pub fn parse_with_options(doc_id: &str, source: impl Into<Arc<str>>, options: &ParseOptions) -> Self {
+        let source: Arc<str> = source.into();
+        let parsed = parser::parse_structure(doc_id, &source, options);
+        let line_starts = parser::line_starts(&source);
+        let offset = |line: usize| line_starts.get(line).copied().unwrap_or(source.len());
+        // The body runs from the line after the heading through end_line, both 1-based
+        let mut body_spans: HashMap<String, (usize, usize, usize)> = parsed
+            .tree
+            .iter()
+            .zip(&parsed.body_indents)
+            .map(|(n, indent)| {
+                let start = offset(n.start_line);
+                (n.node_id.clone(), (start, offset(n.end_line).max(start), *indent))
+            })
+            .collect();
+        // A synthetic root carries the preamble with keep_root and no text otherwise
+        let root = &parsed.tree.root;
+        if root.node_id == "0" {
+            let (first, last) = parsed.preamble_lines;
+            let start = offset(first - 1);
+            let span = if options.keep_root { (start, offset(last).max(start), 0) } else { (0, 0, 0) };
+            body_spans.insert(root.node_id.clone(), span);
+        }
+        LazyDocumentTree {
+            source,
+            structure: parsed.tree,
+            body_spans,
+            options: options.clone(),
+        }
+    }
+
+    // Ids, titles, depths and line spans; every text field is empty
+    pub fn structure(&self) -> &DocumentTree {
+        &self.structure
+    }
+
+    pub fn find_node(&self, node_id: &str) -> Option<&Node> {
+        self.structure.find_node(node_id)
+    }
+
+    // (start, end) byte range of a node's body lines in the source
+    pub fn body_span(&self, node_id: &str) -> Option<(usize, usize)> {
+        self.body_spans.get(node_id).map(|(start, end, _)| (*start, *end))
+    }
+
+    // The node's own text, identical to what the eager parser stores in Node.text
+    pub fn node_text(&self, node_id: &str) -> Option<String> {
+        let (start, end, indent) = *self.body_spans.get(node_id)?;
+        Some(parser::body_text(&self.source[start..end], indent, &self.options))
+    }
+
+    // The shared source, cheap to clone into other lazy views
+    pub fn source(&self) -> Arc<str> {
+        Arc::clone(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_markdown, parse_markdown_with_options};
+
+    const SAMPLE: &str = "# Introduction\nIntroductory text.\n\n## Background\r\nBackground details.\r\n\tIndented.\n\n## Empty\n# Methods\nMethod details.";
+
+    #[test]
+    fn test_lazy_text_matches_eager_parse() {
+        let eager = parse_markdown("doc1", SAMPLE);
+        let lazy = LazyDocumentTree::parse("doc1", SAMPLE);
+        assert_eq!(lazy.structure().all_node_ids(), eager.all_node_ids());
+        for id in eager.all_node_ids() {
+            assert_eq!(lazy.node_text(&id).unwrap(), eager.find_node(&id).unwrap().text, "node {}", id);
+        }
+        assert!(lazy.find_node("1.1").unwrap().text.is_empty());
+        assert!(lazy.node_text("9").is_none());
+    }
+
+    #[test]
+    fn test_lazy_text_matches_eager_parse_with_options() {
+        let md = "---\nid: guide\n---\nPreamble line.\n\n# Guide\nIntro.\n### Skipped\nDeep.\n#### Deeper\nDeepest.\n# Lists\nSteps:\n- First\n  more first\n  - nested\n    nested more\n- Second\n\nAfter the list.\n";
+        let options = ParseOptions::new()
+            .front_matter(true)
+            .normalize_levels(true)
+            .flatten_beyond_depth(Some(2))
+            .list_items_as_nodes(true)
+            .keep_root(true);
+        let eager = parse_markdown_with_options("doc1", md, &options);
+        let lazy = LazyDocumentTree::parse_with_options("doc1", md, &options);
+        assert_eq!(lazy.structure().all_node_ids(), eager.all_node_ids());
+        assert_eq!(lazy.structure().attribute("id"), Some("guide"));
+        for node in eager.iter() {
+            let lazy_node = lazy.find_node(&node.node_id).unwrap();
+            assert_eq!((lazy_node.depth, &lazy_node.title), (node.depth, &node.title));
+            assert_eq!(lazy.node_text(&node.node_id).unwrap(), node.text, "node {}", node.node_id);
+        }
+        assert_eq!(lazy.node_text("0").unwrap(), "Preamble line.");
+        assert_eq!(eager.find_node("2.2").unwrap().text, "nested more");
+
+        let plain = LazyDocumentTree::parse("doc1", "# A\nOne.\n# B\nTwo.");
+        assert_eq!(plain.node_text("0").unwrap(), "");
+    }
+}
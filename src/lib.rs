@@ -1,13 +1,30 @@
-mod tree;
-mod parser;
-mod traversal;
+pub mod tree;
+pub mod parser;
+pub mod traversal;
+pub mod lazy;
+pub mod error;
 
 #[cfg(feature = "extension-module")]
 mod python {
+    use std::collections::{BTreeMap, HashMap};
+
+    use pyo3::exceptions::{PyIOError, PyKeyError, PyTypeError, PyValueError};
     use pyo3::prelude::*;
+    use pyo3::types::PyDict;
     use crate::tree::DocumentTree;
-    use crate::traversal;
-    use crate::parser;
+    use crate::traversal::{self, TraversalResult};
+    use crate::parser::{self, ParseOptions};
+    use crate::error::PageIndexError;
+
+    impl From<PageIndexError> for PyErr {
+        fn from(e: PageIndexError) -> PyErr {
+            match e {
+                PageIndexError::Io(_) => PyIOError::new_err(e.to_string()),
+                PageIndexError::NotFound(_) => PyKeyError::new_err(e.to_string()),
+                PageIndexError::Serde(_) | PageIndexError::Parse(_) => PyValueError::new_err(e.to_string()),
+            }
+        }
+    }
 
     #[pyclass]
     pub struct PageIndex {
@@ -17,19 +34,77 @@ mod python {
     #[pymethods]
     impl PageIndex {
         #[staticmethod]
-        fn from_markdown(doc_id: &str, markdown: &str) -> Self {
-            let tree = parser::parse_markdown(doc_id, markdown);
-            PageIndex { inner: tree }
+        #[pyo3(signature = (doc_id, markdown, **options))]
+        fn from_markdown(doc_id: &str, markdown: &str, options: Option<&Bound<'_, PyDict>>) -> PyResult<Self> {
+            let options = parse_options(options)?;
+            let tree = parser::parse_markdown_with_options(doc_id, markdown, &options);
+            Ok(PageIndex { inner: tree })
+        }
+
+        // Like from_markdown, plus a message for each problem the parse noticed, such as the
+        // unbalanced closing hashes reported under check_closing_hashes=True
+        #[staticmethod]
+        #[pyo3(signature = (doc_id, markdown, **options))]
+        fn parse_with_diagnostics(
+            doc_id: &str,
+            markdown: &str,
+            options: Option<&Bound<'_, PyDict>>,
+        ) -> PyResult<(Self, Vec<String>)> {
+            let options = parse_options(options)?;
+            let (tree, diagnostics) = parser::parse_markdown_with_diagnostics(doc_id, markdown, &options);
+            Ok((PageIndex { inner: tree }, diagnostics.iter().map(|d| d.to_string()).collect()))
+        }
+
+        #[staticmethod]
+        #[pyo3(signature = (doc_id, path, **options))]
+        fn from_file(doc_id: &str, path: &str, options: Option<&Bound<'_, PyDict>>) -> PyResult<Self> {
+            let options = parse_options(options)?;
+            let content = std::fs::read_to_string(path).map_err(PageIndexError::from)?;
+            let tree = parser::parse_markdown_with_options(doc_id, &content, &options);
+            Ok(PageIndex { inner: tree })
+        }
+
+        // One index per document in a file of documents separated by `delimiter` lines
+        #[staticmethod]
+        #[pyo3(signature = (text, delimiter="---"))]
+        fn parse_multi(text: &str, delimiter: &str) -> Vec<PageIndex> {
+            parser::parse_multi(text, delimiter)
+                .into_iter()
+                .map(|tree| PageIndex { inner: tree })
+                .collect()
+        }
+
+        // Parses many files in parallel; doc_ids are the file stems. Returns one entry per path, in
+        // the order of `paths`: the index, or for a file that can't be read the exception
+        // (IOError) that from_file would raise, so one bad file never costs the rest of the batch.
+        #[staticmethod]
+        fn parse_files(py: Python<'_>, paths: Vec<std::path::PathBuf>) -> PyResult<Vec<PyObject>> {
+            parser::parse_files(&paths)
+                .into_iter()
+                .map(|tree| match tree {
+                    Ok(tree) => Ok(Py::new(py, PageIndex { inner: tree })?.into_py(py)),
+                    Err(e) => Ok(PyErr::from(e).into_value(py).into_py(py)),
+                })
+                .collect()
         }
 
         #[staticmethod]
-        fn from_file(doc_id: &str, path: &str) -> PyResult<Self> {
-            let content = std::fs::read_to_string(path)
-                .map_err(|e| pyo3::exceptions::PyIOError::new_err(e.to_string()))?;
-            let tree = parser::parse_markdown(doc_id, &content);
+        fn from_flat_json(json: &str) -> PyResult<Self> {
+            let tree = DocumentTree::from_flat_json(json)?;
             Ok(PageIndex { inner: tree })
         }
 
+        #[staticmethod]
+        fn from_json(json: &str) -> PyResult<Self> {
+            Ok(PageIndex { inner: DocumentTree::from_json(json)? })
+        }
+
+        #[staticmethod]
+        fn merge(doc_id: &str, title: &str, indexes: Vec<PyRef<'_, PageIndex>>) -> Self {
+            let trees = indexes.iter().map(|index| index.inner.clone()).collect();
+            PageIndex { inner: DocumentTree::merge(doc_id, title, trees) }
+        }
+
         fn title(&self) -> String {
             self.inner.title.clone()
         }
@@ -38,39 +113,305 @@ mod python {
             traversal::get_tree_outline(&self.inner)
         }
 
+        // The outline down to `max_depth`, with ` …` after sections whose children are hidden
+        fn outline_collapsed(&self, max_depth: usize) -> String {
+            traversal::get_tree_outline_collapsed(&self.inner, max_depth)
+        }
+
         fn node_ids(&self) -> Vec<String> {
             self.inner.all_node_ids()
         }
 
-        fn get_node(&self, node_id: &str) -> Option<PyNodeResult> {
-            traversal::get_node(&self.inner, node_id).map(|r| PyNodeResult {
-                node_id: r.node_id,
-                title: r.title,
-                text: r.text,
-                depth: r.depth,
-                breadcrumb: r.breadcrumb,
-            })
+        fn node_count(&self) -> usize {
+            self.inner.node_count()
+        }
+
+        // Levels of nesting below the document, 0 when there are no sections
+        fn max_depth(&self) -> usize {
+            self.inner.max_depth()
+        }
+
+        fn contains(&self, node_id: &str) -> bool {
+            self.inner.contains(node_id)
+        }
+
+        fn __len__(&self) -> usize {
+            self.inner.node_count()
+        }
+
+        fn __contains__(&self, node_id: &str) -> bool {
+            self.inner.contains(node_id)
+        }
+
+        // Results are built up front, so the iterator holds no borrow of the index
+        fn __iter__(&self) -> NodeIter {
+            NodeIter {
+                results: self.iter_nodes().into_iter(),
+            }
+        }
+
+        // Node ids level by level, top-level sections first
+        fn bfs_ids(&self) -> Vec<String> {
+            self.inner.bfs_nodes().iter().map(|n| n.node_id.clone()).collect()
+        }
+
+        fn leaf_ids(&self) -> Vec<String> {
+            self.inner.leaves().iter().map(|n| n.node_id.clone()).collect()
+        }
+
+        // Every node in document order, in one pass rather than get_node per id
+        fn iter_nodes(&self) -> Vec<PyNodeResult> {
+            traversal::all_results(&self.inner)
+                .into_iter()
+                .map(PyNodeResult::from)
+                .collect()
+        }
+
+        // With preview_chars the text is cut to that many characters, ending in `…`
+        #[pyo3(signature = (node_id, preview_chars=None))]
+        fn get_node(&self, node_id: &str, preview_chars: Option<usize>) -> Option<PyNodeResult> {
+            match preview_chars {
+                Some(max_chars) => traversal::get_node_preview(&self.inner, node_id, max_chars),
+                None => traversal::get_node(&self.inner, node_id),
+            }
+            .map(PyNodeResult::from)
+        }
+
+        // The node's summary if it has one, else its text
+        fn get_content(&self, node_id: &str) -> Option<String> {
+            traversal::get_node(&self.inner, node_id).map(|r| r.best_content().to_string())
         }
 
         fn get_node_with_children(&self, node_id: &str) -> Option<PyNodeResult> {
-            traversal::get_node_with_children(&self.inner, node_id).map(|r| PyNodeResult {
-                node_id: r.node_id,
-                title: r.title,
-                text: r.text,
-                depth: r.depth,
-                breadcrumb: r.breadcrumb,
-            })
+            traversal::get_node_with_children(&self.inner, node_id).map(PyNodeResult::from)
+        }
+
+        // The sections for `node_ids` under breadcrumb headers, skipping ones an earlier or later
+        // ancestor already covers, within roughly `max_tokens` tokens
+        fn assemble_context(&self, node_ids: Vec<String>, max_tokens: usize) -> String {
+            traversal::assemble_context(&self.inner, &node_ids, max_tokens)
+        }
+
+        // Ids of every section with this title, in document order
+        #[pyo3(signature = (title, case_insensitive=true))]
+        fn find_by_title(&self, title: &str, case_insensitive: bool) -> Vec<String> {
+            self.inner
+                .find_nodes_by_title(title, case_insensitive)
+                .iter()
+                .map(|n| n.node_id.clone())
+                .collect()
+        }
+
+        fn find_by_title_path(&self, titles: Vec<String>) -> Option<PyNodeResult> {
+            let titles: Vec<&str> = titles.iter().map(|t| t.as_str()).collect();
+            let node_id = traversal::find_by_title_path(&self.inner, &titles)?.node_id.clone();
+            traversal::get_node(&self.inner, &node_id).map(PyNodeResult::from)
+        }
+
+        fn node_at_line(&self, line: usize) -> Option<PyNodeResult> {
+            let node_id = traversal::node_at_line(&self.inner, line)?.node_id.clone();
+            traversal::get_node(&self.inner, &node_id).map(PyNodeResult::from)
+        }
+
+        // (node_id, title) pairs from the top-level section down to this node
+        fn get_path(&self, node_id: &str) -> Vec<(String, String)> {
+            traversal::get_path(&self.inner, node_id)
+        }
+
+        #[pyo3(signature = (node_id, sep=" > "))]
+        fn breadcrumb(&self, node_id: &str, sep: &str) -> Option<String> {
+            traversal::get_node(&self.inner, node_id).map(|r| r.breadcrumb_string(sep))
+        }
+
+        // Document-unique slug, with `-1`, `-2`, ... appended to repeated titles
+        fn slug(&self, node_id: &str) -> Option<String> {
+            self.inner.unique_slugs().remove(node_id)
+        }
+
+        // (text, url) pairs of the inline links in one section's own text
+        fn links(&self, node_id: &str) -> Vec<(String, String)> {
+            self.inner.find_node(node_id).map(|n| n.links()).unwrap_or_default()
+        }
+
+        // The n most frequent non-stopword words of a section's own text, with counts
+        fn keywords(&self, node_id: &str, n: usize) -> Vec<(String, usize)> {
+            self.inner.find_node(node_id).map(|node| node.top_keywords(n)).unwrap_or_default()
+        }
+
+        // (alt, src) pairs of the inline images in one section's own text
+        fn images(&self, node_id: &str) -> Vec<(String, String)> {
+            self.inner.find_node(node_id).map(|n| n.images()).unwrap_or_default()
         }
 
         fn get_children(&self, node_id: &str) -> Vec<(String, String)> {
             traversal::get_children(&self.inner, node_id)
         }
 
-        fn to_json(&self) -> String {
-            self.inner.to_json()
+        fn get_children_detailed(&self, node_id: &str) -> Vec<PyNodeResult> {
+            traversal::get_children_detailed(&self.inner, node_id)
+                .into_iter()
+                .map(PyNodeResult::from)
+                .collect()
+        }
+
+        fn subtree(&self, node_id: &str) -> Option<PageIndex> {
+            self.inner.subtree(node_id).map(|tree| PageIndex { inner: tree })
+        }
+
+        // Appends a section under `parent_id` and returns its id, or None for an unknown parent
+        fn add_child(&mut self, parent_id: &str, title: &str, text: &str) -> Option<String> {
+            self.inner.add_child(parent_id, title, text)
+        }
+
+        fn update_text(&mut self, node_id: &str, new_text: String) -> bool {
+            self.inner.update_text(node_id, new_text)
+        }
+
+        // The node keeps its id
+        fn update_title(&mut self, node_id: &str, new_title: &str) -> bool {
+            self.inner.update_title(node_id, new_title)
+        }
+
+        fn delete_node(&mut self, node_id: &str) -> bool {
+            self.inner.delete_node(node_id)
+        }
+
+        fn move_node(&mut self, node_id: &str, new_parent_id: &str) -> bool {
+            self.inner.move_node(node_id, new_parent_id)
+        }
+
+        // A copy without empty leaf sections, renumbered
+        fn pruned(&self) -> PageIndex {
+            let mut tree = self.inner.clone();
+            tree.prune_empty();
+            PageIndex { inner: tree }
+        }
+
+        // Returns (added, removed, modified) node ids going from this index to `new`
+        fn diff(&self, new: PyRef<'_, PageIndex>) -> (Vec<String>, Vec<String>, Vec<String>) {
+            let d = traversal::diff(&self.inner, &new.inner);
+            (d.added, d.removed, d.modified)
+        }
+
+        // Minutes to read a section at `wpm`, optionally including its subsections
+        #[pyo3(signature = (node_id, wpm=200, include_children=false))]
+        fn reading_time(&self, node_id: &str, wpm: usize, include_children: bool) -> Option<f32> {
+            let node = self.inner.find_node(node_id)?;
+            Some(if include_children {
+                node.subtree_reading_time_minutes(wpm)
+            } else {
+                node.reading_time_minutes(wpm)
+            })
+        }
+
+        // max_depth, node_count, leaf_count, max_fanout and avg_fanout of the parsed structure
+        fn stats<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+            let stats = self.inner.structure_stats();
+            let dict = PyDict::new_bound(py);
+            dict.set_item("max_depth", stats.max_depth)?;
+            dict.set_item("node_count", stats.node_count)?;
+            dict.set_item("leaf_count", stats.leaf_count)?;
+            dict.set_item("max_fanout", stats.max_fanout)?;
+            dict.set_item("avg_fanout", stats.avg_fanout)?;
+            Ok(dict)
+        }
+
+        // Pages of the source document a section covers, for citations; False for an unknown id
+        fn set_page_span(&mut self, node_id: &str, start_page: usize, end_page: usize) -> bool {
+            self.inner.set_page_span(node_id, start_page, end_page)
+        }
+
+        // (node_id, node_id) pairs whose texts share more than `threshold` of their words
+        fn find_duplicates(&self, threshold: f32) -> Vec<(String, String)> {
+            self.inner.find_duplicates(threshold)
+        }
+
+        // The node's metadata dict, or None for an unknown id
+        fn get_metadata(&self, node_id: &str) -> Option<BTreeMap<String, String>> {
+            self.inner.find_node(node_id).map(|n| n.metadata.clone())
+        }
+
+        fn set_metadata(&mut self, node_id: &str, key: &str, value: &str) -> bool {
+            self.inner.set_metadata(node_id, key, value)
+        }
+
+        fn node_hashes(&self) -> HashMap<String, u64> {
+            self.inner.node_hashes()
+        }
+
+        fn attributes(&self) -> BTreeMap<String, String> {
+            self.inner.attributes.clone()
+        }
+
+        fn get_attribute(&self, key: &str) -> Option<String> {
+            self.inner.attribute(key).map(|v| v.to_string())
+        }
+
+        fn set_attribute(&mut self, key: &str, value: &str) {
+            self.inner.set_attribute(key, value);
+        }
+
+        fn to_flat_json(&self) -> PyResult<String> {
+            Ok(self.inner.try_to_flat_json()?)
+        }
+
+        fn to_csv(&self) -> String {
+            self.inner.to_csv()
+        }
+
+        fn to_dot(&self) -> String {
+            self.inner.to_dot()
+        }
+
+        #[pyo3(signature = (include_text=true))]
+        fn to_json(&self, include_text: bool) -> PyResult<String> {
+            if include_text {
+                Ok(self.inner.try_to_json()?)
+            } else {
+                Ok(self.inner.try_to_json_outline_only()?)
+            }
         }
     }
 
+    // Maps `from_markdown`/`from_file` keyword arguments onto ParseOptions fields
+    fn parse_options(kwargs: Option<&Bound<'_, PyDict>>) -> PyResult<ParseOptions> {
+        let mut options = ParseOptions::default();
+        let Some(kwargs) = kwargs else {
+            return Ok(options);
+        };
+        for (key, value) in kwargs.iter() {
+            let key: String = key.extract()?;
+            match key.as_str() {
+                "normalize_levels" => options.normalize_levels = value.extract()?,
+                "promote_single_root" => options.promote_single_root = value.extract()?,
+                "flatten_beyond_depth" => options.flatten_beyond_depth = value.extract()?,
+                "max_heading_depth" => options.max_heading_depth = value.extract()?,
+                "bold_heading_depth" => options.bold_heading_depth = value.extract()?,
+                "check_closing_hashes" => options.check_closing_hashes = value.extract()?,
+                "front_matter" => options.front_matter = value.extract()?,
+                "keep_root" => options.keep_root = value.extract()?,
+                "tab_width" => options.tab_width = value.extract()?,
+                "strip_leading_numbers" => options.strip_leading_numbers = value.extract()?,
+                "id_separator" => options.id_separator = value.extract()?,
+                "collapse_title_whitespace" => options.collapse_title_whitespace = value.extract()?,
+                "collapse_blank_lines" => options.collapse_blank_lines = value.extract()?,
+                "dedent" => options.dedent = value.extract()?,
+                "root_title" => options.root_title = value.extract()?,
+                "synthesize_root_from_doc_id" => options.synthesize_root_from_doc_id = value.extract()?,
+                "require_space_after_hash" => options.require_space_after_hash = value.extract()?,
+                "list_items_as_nodes" => options.list_items_as_nodes = value.extract()?,
+                "page_markers" => options.page_markers = value.extract()?,
+                _ => {
+                    return Err(PyTypeError::new_err(format!(
+                        "unexpected parse option '{}'",
+                        key
+                    )))
+                }
+            }
+        }
+        Ok(options)
+    }
+
     #[pyclass]
     #[derive(Clone)]
     pub struct PyNodeResult {
@@ -84,6 +425,36 @@ mod python {
         pub depth: usize,
         #[pyo3(get)]
         pub breadcrumb: Vec<String>,
+        #[pyo3(get)]
+        pub slug: String,
+        #[pyo3(get)]
+        pub start_line: usize,
+        #[pyo3(get)]
+        pub end_line: usize,
+        #[pyo3(get)]
+        pub page_start: Option<usize>,
+        #[pyo3(get)]
+        pub page_end: Option<usize>,
+        #[pyo3(get)]
+        pub metadata: BTreeMap<String, String>,
+    }
+
+    impl From<TraversalResult> for PyNodeResult {
+        fn from(r: TraversalResult) -> Self {
+            PyNodeResult {
+                node_id: r.node_id,
+                title: r.title,
+                text: r.text,
+                depth: r.depth,
+                breadcrumb: r.breadcrumb,
+                slug: r.slug,
+                start_line: r.start_line,
+                end_line: r.end_line,
+                page_start: r.page_start,
+                page_end: r.page_end,
+                metadata: r.metadata,
+            }
+        }
     }
 
     #[pymethods]
@@ -96,10 +467,27 @@ mod python {
         }
     }
 
+    #[pyclass]
+    pub struct NodeIter {
+        results: std::vec::IntoIter<PyNodeResult>,
+    }
+
+    #[pymethods]
+    impl NodeIter {
+        fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+            slf
+        }
+
+        fn __next__(mut slf: PyRefMut<'_, Self>) -> Option<PyNodeResult> {
+            slf.results.next()
+        }
+    }
+
     #[pymodule]
     pub fn pageindex_rs(_py: Python, m: &PyModule) -> PyResult<()> {
         m.add_class::<PageIndex>()?;
         m.add_class::<PyNodeResult>()?;
+        m.add_class::<NodeIter>()?;
         Ok(())
     }
 }
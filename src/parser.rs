@@ -1,81 +1,971 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+
+use rayon::prelude::*;
+
+use crate::error::PageIndexError;
 use crate::tree::{DocumentTree, Node};
 
+// Independent parser behaviors. `ParseOptions::default()` matches `parse_markdown`; the chainable
+// setters make it easy to flip just the ones you need.
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    // Collapse skipped heading levels, see parse_markdown_normalized
+    pub normalize_levels: bool,
+    // Replace the synthetic root with the single top-level section when there is exactly one.
+    // On by default for back-compat; turn it off so every tree has a node `0` root whose
+    // children are the top-level sections, regardless of how many there are.
+    pub promote_single_root: bool,
+    // Headings deeper than this are kept as nodes but clamped to this depth, becoming siblings
+    // of the section they would otherwise nest under
+    pub flatten_beyond_depth: Option<usize>,
+    // Headings deeper than this stay in the enclosing section's text, `#` markers included,
+    // instead of becoming nodes
+    pub max_heading_depth: Option<usize>,
+    // Treat a line consisting only of `**Title**` (or `__Title__`) as a heading at this depth,
+    // recovering structure from documents converted without real headings
+    pub bold_heading_depth: Option<usize>,
+    // Report a diagnostic when an ATX closing sequence (`## Title ####`) has a different number of
+    // hashes than the opening one. Off by default.
+    pub check_closing_hashes: bool,
+    // Read a leading `---` block of `key: value` lines into DocumentTree.attributes
+    pub front_matter: bool,
+    // Always keep node `0` as the root, carrying the document title and any text before the first
+    // heading, so it can be fetched like any other node. Implies no root promotion.
+    pub keep_root: bool,
+    // Column width tabs in leading indentation expand to, so `\t# Title` or a tab-indented code
+    // line is judged by the same 3/4-space rules as its space-indented equivalent
+    pub tab_width: usize,
+    // Remove manual numbering like `2.1 ` from the start of titles, since it duplicates the
+    // generated ids. The heading as written is kept in Node.raw_title.
+    pub strip_leading_numbers: bool,
+    // Placed between the parts of generated ids, `.` by default (`2.1`). Use e.g. `-` for
+    // downstream systems that can't have dots in identifiers; the tree keeps it for renumbering.
+    pub id_separator: String,
+    // Turn runs of spaces or tabs inside a title into single spaces (`Goals    and   Objectives`
+    // becomes `Goals and Objectives`). On by default; bodies are never touched.
+    pub collapse_title_whitespace: bool,
+    // Squeeze each run of blank lines in node text down to a single blank line
+    pub collapse_blank_lines: bool,
+    // Strip the indentation common to all non-blank body lines, after tab expansion
+    pub dedent: bool,
+    // DocumentTree.title for documents with several top-level sections, in place of the first
+    // section's title. Single-section documents still take their title from that section.
+    pub root_title: Option<String>,
+    // With several top-level sections, title the synthetic node `0` with the doc_id instead of
+    // "root", so every tree has a single named root node
+    pub synthesize_root_from_doc_id: bool,
+    // Only treat `#` lines as headings when a space or tab follows the hashes, rejecting
+    // `#Heading`. Off by default, which keeps the lenient behavior.
+    pub require_space_after_hash: bool,
+    // Turn the items of the first list in each section into child nodes one level deeper, titled
+    // with the item's first line, and items of lists nested in those into grandchildren. Deeper
+    // lists stay in their item's text, as does any text after the list, which joins the last item.
+    pub list_items_as_nodes: bool,
+    // Read `<!-- page: 12 -->` lines, as PDF converters emit at page breaks, into Node.page_start
+    // and page_end: a section starts on the page in effect at its heading and ends on the page of
+    // its last content line. The marker lines are dropped from node text.
+    pub page_markers: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Diagnostic {
+    ClosingHashMismatch { line: usize, opening: usize, closing: usize },
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Diagnostic::ClosingHashMismatch { line, opening, closing } => write!(
+                f,
+                "line {}: heading opens with {} '#' but closes with {}",
+                line, opening, closing
+            ),
+        }
+    }
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            normalize_levels: false,
+            promote_single_root: true,
+            flatten_beyond_depth: None,
+            max_heading_depth: None,
+            bold_heading_depth: None,
+            check_closing_hashes: false,
+            front_matter: false,
+            keep_root: false,
+            tab_width: 4,
+            strip_leading_numbers: false,
+            id_separator: ".".to_string(),
+            collapse_title_whitespace: true,
+            collapse_blank_lines: false,
+            dedent: false,
+            root_title: None,
+            synthesize_root_from_doc_id: false,
+            require_space_after_hash: false,
+            list_items_as_nodes: false,
+            page_markers: false,
+        }
+    }
+}
+
+impl ParseOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn normalize_levels(mut self, enabled: bool) -> Self {
+        self.normalize_levels = enabled;
+        self
+    }
+
+    pub fn promote_single_root(mut self, enabled: bool) -> Self {
+        self.promote_single_root = enabled;
+        self
+    }
+
+    pub fn flatten_beyond_depth(mut self, cap: Option<usize>) -> Self {
+        self.flatten_beyond_depth = cap;
+        self
+    }
+
+    pub fn max_heading_depth(mut self, max: Option<usize>) -> Self {
+        self.max_heading_depth = max;
+        self
+    }
+
+    pub fn bold_heading_depth(mut self, depth: Option<usize>) -> Self {
+        self.bold_heading_depth = depth;
+        self
+    }
+
+    pub fn check_closing_hashes(mut self, enabled: bool) -> Self {
+        self.check_closing_hashes = enabled;
+        self
+    }
+
+    pub fn front_matter(mut self, enabled: bool) -> Self {
+        self.front_matter = enabled;
+        self
+    }
+
+    pub fn keep_root(mut self, enabled: bool) -> Self {
+        self.keep_root = enabled;
+        self
+    }
+
+    pub fn tab_width(mut self, width: usize) -> Self {
+        self.tab_width = width;
+        self
+    }
+
+    pub fn strip_leading_numbers(mut self, enabled: bool) -> Self {
+        self.strip_leading_numbers = enabled;
+        self
+    }
+
+    pub fn id_separator(mut self, separator: &str) -> Self {
+        self.id_separator = separator.to_string();
+        self
+    }
+
+    pub fn collapse_title_whitespace(mut self, enabled: bool) -> Self {
+        self.collapse_title_whitespace = enabled;
+        self
+    }
+
+    pub fn collapse_blank_lines(mut self, enabled: bool) -> Self {
+        self.collapse_blank_lines = enabled;
+        self
+    }
+
+    pub fn dedent(mut self, enabled: bool) -> Self {
+        self.dedent = enabled;
+        self
+    }
+
+    pub fn root_title(mut self, title: Option<&str>) -> Self {
+        self.root_title = title.map(str::to_string);
+        self
+    }
+
+    pub fn synthesize_root_from_doc_id(mut self, enabled: bool) -> Self {
+        self.synthesize_root_from_doc_id = enabled;
+        self
+    }
+
+    pub fn require_space_after_hash(mut self, enabled: bool) -> Self {
+        self.require_space_after_hash = enabled;
+        self
+    }
+
+    pub fn list_items_as_nodes(mut self, enabled: bool) -> Self {
+        self.list_items_as_nodes = enabled;
+        self
+    }
+
+    pub fn page_markers(mut self, enabled: bool) -> Self {
+        self.page_markers = enabled;
+        self
+    }
+}
+
 pub fn parse_markdown(doc_id: &str, markdown: &str) -> DocumentTree {
-    let mut blocks: Vec<(usize, String, String)> = Vec::new();
-    let mut doc_title = doc_id.to_string();
-
-    let mut current_depth: usize = 0;
-    let mut current_title = String::new();
-    let mut current_body: Vec<&str> = Vec::new();
-    let mut started = false;
-
-    for line in markdown.lines() {
-        if let Some((depth, title)) = parse_heading(line) {
-            if started {
-                blocks.push((
-                    current_depth,
-                    current_title.clone(),
-                    current_body.join("\n").trim().to_string(),
-                ));
+    parse_markdown_with_options(doc_id, markdown, &ParseOptions::default())
+}
+
+pub fn parse_markdown_with_options(doc_id: &str, markdown: &str, options: &ParseOptions) -> DocumentTree {
+    parse_markdown_with_diagnostics(doc_id, markdown, options).0
+}
+
+// Like parse_markdown_with_options, also returning any diagnostics the enabled checks produced
+pub fn parse_markdown_with_diagnostics(
+    doc_id: &str,
+    markdown: &str,
+    options: &ParseOptions,
+) -> (DocumentTree, Vec<Diagnostic>) {
+    let mut diagnostics = Vec::new();
+    let (_, scan) = scan_document(markdown, options, &mut diagnostics, true);
+    (tree_from_scan(doc_id, markdown, &scan, options), diagnostics)
+}
+
+// Every block-level step of parsing, shared by the eager and lazy parsers so both agree on ids,
+// depths and line spans. Returns the number of front matter lines and the scan; with `bodies`
+// off, block bodies and the preamble stay empty.
+fn scan_document(
+    markdown: &str,
+    options: &ParseOptions,
+    diagnostics: &mut Vec<Diagnostic>,
+    bodies: bool,
+) -> (usize, DocumentScan) {
+    let (attributes, skip_lines) = if options.front_matter {
+        parse_front_matter(markdown)
+    } else {
+        (BTreeMap::new(), 0)
+    };
+    let mut scan = scan_markdown(markdown, skip_lines, options, diagnostics, bodies);
+    if options.list_items_as_nodes {
+        scan.blocks = split_list_items(markdown, std::mem::take(&mut scan.blocks), options, bodies);
+    }
+    if options.page_markers {
+        assign_pages(markdown, &mut scan.blocks);
+    }
+    if options.normalize_levels {
+        normalize_depths(&mut scan.blocks);
+    }
+    if let Some(cap) = options.flatten_beyond_depth {
+        for block in scan.blocks.iter_mut() {
+            block.depth = block.depth.min(cap.max(1));
+        }
+    }
+    (skip_lines, DocumentScan { attributes, scan })
+}
+
+fn tree_from_scan(doc_id: &str, markdown: &str, document: &DocumentScan, options: &ParseOptions) -> DocumentTree {
+    let mut tree = tree_from_blocks(doc_id, markdown, &document.scan, options);
+    tree.attributes = document.attributes.clone();
+    tree
+}
+
+// Like parse_markdown, but collapses skipped heading levels first. With raw depths a `#` followed
+// by `###` yields ids `1` and `1.0.1`; normalized, the `###` becomes a depth-2 child with id `1.1`.
+// Depths in the resulting tree are structural levels rather than the original `#` counts.
+pub fn parse_markdown_normalized(doc_id: &str, markdown: &str) -> DocumentTree {
+    parse_markdown_with_options(doc_id, markdown, &ParseOptions::new().normalize_levels(true))
+}
+
+// Builds a tree from any line-oriented text, with `detector` deciding which lines are headings in
+// place of markdown's `#` rule. It returns the heading's depth (1 = top level) and title; every
+// other line becomes body text of the heading above it. Default options apply otherwise.
+pub fn parse_with_detector(
+    doc_id: &str,
+    text: &str,
+    detector: impl Fn(&str) -> Option<(usize, String)>,
+) -> DocumentTree {
+    let options = ParseOptions::default();
+    let scan = scan_blocks(text, 0, &options, &mut Vec::new(), &detector, true);
+    tree_from_blocks(doc_id, text, &scan, &options)
+}
+
+// Parses a file holding several documents, split at lines consisting only of `delimiter`. Blank
+// chunks are skipped. Each chunk may start with front matter; its `id` becomes the doc_id,
+// otherwise the doc_id is the chunk's 0-based position among the documents returned. A `---`
+// delimiter that opens a front matter block (`key: value` lines up to a closing fence) starts a
+// new document carrying that block, so the default delimiter keeps each document's front matter.
+pub fn parse_multi(text: &str, delimiter: &str) -> Vec<DocumentTree> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut chunks: Vec<String> = vec![String::new()];
+    let mut index = 0;
+    while index < lines.len() {
+        let line = lines[index];
+        if line.trim() == delimiter {
+            let front_matter = front_matter_len(&lines[index..]);
+            let mut chunk = String::new();
+            for line in &lines[index..index + front_matter] {
+                chunk.push_str(line);
+                chunk.push('\n');
+            }
+            chunks.push(chunk);
+            index += front_matter.max(1);
+            continue;
+        }
+        let chunk = chunks.last_mut().expect("chunks starts non-empty");
+        chunk.push_str(line);
+        chunk.push('\n');
+        index += 1;
+    }
+    let options = ParseOptions::new().front_matter(true);
+    chunks
+        .iter()
+        .filter(|chunk| !chunk.trim().is_empty())
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut tree = parse_markdown_with_options(&index.to_string(), chunk, &options);
+            if let Some(id) = tree.attribute("id").map(str::to_string) {
+                tree.doc_id = id;
             }
+            tree
+        })
+        .collect()
+}
 
-            if !started && depth == 1 {
-                doc_title = title.clone();
+// Lines taken by a front matter block at the start of `lines`, fences included, or 0 when they
+// don't open one. Stricter than parse_front_matter, which reads past stray lines: a block here
+// needs at least one `key: value` line and nothing else, so a `---` rule between documents
+// followed by ordinary text is never mistaken for one.
+fn front_matter_len(lines: &[&str]) -> usize {
+    if lines.first().map(|l| l.trim_end()) != Some("---") {
+        return 0;
+    }
+    let is_key_line = |line: &str| {
+        line.split_once(':')
+            .is_some_and(|(key, _)| !key.trim().is_empty() && !key.trim().contains(char::is_whitespace))
+    };
+    let mut keys = 0;
+    for (index, line) in lines.iter().enumerate().skip(1) {
+        let line = line.trim();
+        if line == "---" || line == "..." {
+            return if keys > 0 { index + 1 } else { 0 };
+        }
+        if is_key_line(line) {
+            keys += 1;
+        } else if !line.is_empty() {
+            return 0;
+        }
+    }
+    0
+}
+
+// Reads and parses every file in parallel with default options, using each file stem as the
+// doc_id. Results are in the order of `paths`; a file that can't be read gives an Io error in its
+// slot without affecting the others.
+pub fn parse_files(paths: &[PathBuf]) -> Vec<Result<DocumentTree, PageIndexError>> {
+    paths
+        .par_iter()
+        .map(|path| {
+            let markdown = std::fs::read_to_string(path)?;
+            let doc_id = path.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+            Ok(parse_markdown(&doc_id, &markdown))
+        })
+        .collect()
+}
+
+// Mirrors the original PageIndex "page -> sections" model: headings at the shallowest level become
+// depth-1 pages and every deeper heading becomes a depth-2 section under the nearest preceding page
+pub fn parse_as_pages(doc_id: &str, markdown: &str) -> DocumentTree {
+    let options = ParseOptions::default();
+    let mut scan = scan_markdown(markdown, 0, &options, &mut Vec::new(), true);
+    if let Some(page_depth) = scan.blocks.iter().map(|b| b.depth).min() {
+        for block in scan.blocks.iter_mut() {
+            block.depth = if block.depth == page_depth { 1 } else { 2 };
+        }
+    }
+    tree_from_blocks(doc_id, markdown, &scan, &options)
+}
+
+// A scan plus the front matter attributes read ahead of it
+struct DocumentScan {
+    attributes: BTreeMap<String, String>,
+    scan: Scan,
+}
+
+// Text before the first heading, plus one block per heading
+struct Scan {
+    preamble: String,
+    blocks: Vec<Block>,
+}
+
+// One heading and the body lines owned by it, before tree construction
+struct Block {
+    depth: usize,
+    title: String,
+    raw_title: Option<String>,
+    body: String,
+    start_line: usize,
+    end_line: usize,
+    page_start: Option<usize>,
+    page_end: Option<usize>,
+    // Leading columns removed from the body lines before joining them, nonzero for list items
+    body_indent: usize,
+}
+
+// Lines before `skip_lines` (front matter) are counted but never scanned for headings
+fn scan_markdown(
+    markdown: &str,
+    skip_lines: usize,
+    options: &ParseOptions,
+    diagnostics: &mut Vec<Diagnostic>,
+    bodies: bool,
+) -> Scan {
+    let detect = |line: &str| {
+        let heading = parse_heading(line, options.require_space_after_hash).or_else(|| {
+            let depth = options.bold_heading_depth?;
+            parse_bold_heading(line).map(|title| (depth.max(1), title))
+        });
+        heading.filter(|(depth, _)| options.max_heading_depth.is_none_or(|max| *depth <= max))
+    };
+    scan_blocks(markdown, skip_lines, options, diagnostics, &detect, bodies)
+}
+
+// The tree parse_markdown_with_options would build, minus all body text: nodes keep their
+// titles, ids and line spans, and body_text recreates a body from its source lines on demand
+pub(crate) struct Structure {
+    pub(crate) tree: DocumentTree,
+    // Per real node in document order, the leading columns body_text must strip from its lines
+    pub(crate) body_indents: Vec<usize>,
+    // 1-based, inclusive lines holding the preamble (empty when the first is past the last)
+    pub(crate) preamble_lines: (usize, usize),
+}
+
+pub(crate) fn parse_structure(doc_id: &str, markdown: &str, options: &ParseOptions) -> Structure {
+    let (skip_lines, document) = scan_document(markdown, options, &mut Vec::new(), false);
+    let preamble_end = document
+        .scan
+        .blocks
+        .first()
+        .map_or(markdown.lines().count(), |b| b.start_line - 1);
+    Structure {
+        tree: tree_from_scan(doc_id, markdown, &document, options),
+        body_indents: document.scan.blocks.iter().map(|b| b.body_indent).collect(),
+        preamble_lines: (skip_lines + 1, preamble_end),
+    }
+}
+
+// A node's body exactly as the eager parser stores it, given the source lines it spans and the
+// indent parsing stripped from them (list item continuation lines lose their marker's width)
+pub(crate) fn body_text(lines: &str, indent: usize, options: &ParseOptions) -> String {
+    let lines: Vec<Cow<str>> = lines
+        .lines()
+        .map(|l| strip_indent(expand_leading_tabs(l, options.tab_width), indent))
+        .collect();
+    finish_body(&lines, options)
+}
+
+// Removes up to `columns` leading spaces
+fn strip_indent(line: Cow<str>, columns: usize) -> Cow<str> {
+    let strip = (line.len() - line.trim_start_matches(' ').len()).min(columns);
+    match line {
+        _ if strip == 0 => line,
+        Cow::Borrowed(l) => Cow::Borrowed(&l[strip..]),
+        Cow::Owned(l) => Cow::Owned(l[strip..].to_string()),
+    }
+}
+
+// Joins a block's body lines into node text, applying the whitespace options, then trims it
+fn finish_body(lines: &[Cow<str>], options: &ParseOptions) -> String {
+    let is_blank = |line: &str| line.trim().is_empty();
+    // Page markers are dropped from the body, so their indent must not limit the dedent
+    let is_marker = |line: &str| options.page_markers && page_marker(line).is_some();
+    let dedent = if options.dedent {
+        lines
+            .iter()
+            .filter(|l| !is_blank(l) && !is_marker(l))
+            .map(|l| l.len() - l.trim_start_matches(' ').len())
+            .min()
+            .unwrap_or(0)
+    } else {
+        0
+    };
+    let mut kept: Vec<&str> = Vec::with_capacity(lines.len());
+    for line in lines {
+        if is_marker(line) {
+            continue;
+        }
+        if is_blank(line) {
+            if options.collapse_blank_lines && kept.last().is_some_and(|l| l.is_empty()) {
+                continue;
             }
+            kept.push(if options.collapse_blank_lines { "" } else { line });
+        } else {
+            kept.push(&line[dedent..]);
+        }
+    }
+    kept.join("\n").trim().to_string()
+}
+
+// Splits text into blocks at every line `detect` reports as a heading. With `bodies` off only
+// headings and line spans are recorded, leaving every body (and the preamble) empty.
+fn scan_blocks(
+    markdown: &str,
+    skip_lines: usize,
+    options: &ParseOptions,
+    diagnostics: &mut Vec<Diagnostic>,
+    detect: &dyn Fn(&str) -> Option<(usize, String)>,
+    bodies: bool,
+) -> Scan {
+    let mut blocks: Vec<Block> = Vec::new();
+    let mut body: Vec<Cow<str>> = Vec::new();
+    let mut preamble = String::new();
+    let mut line_count = skip_lines;
+
+    for (index, line) in markdown.lines().enumerate().skip(skip_lines) {
+        let line_no = index + 1;
+        line_count = line_no;
+        let line = expand_leading_tabs(line, options.tab_width);
+        if let Some((depth, title)) = detect(&line) {
+            let depth = depth.max(1);
+            if options.check_closing_hashes {
+                if let Some(closing) = closing_hash_run(line.trim_end()).filter(|c| *c != depth) {
+                    diagnostics.push(Diagnostic::ClosingHashMismatch { line: line_no, opening: depth, closing });
+                }
+            }
+            if blocks.is_empty() {
+                preamble = finish_body(&body, options);
+            }
+            close_block(&mut blocks, &mut body, line_no - 1, options);
+            let title = if options.collapse_title_whitespace {
+                title.split_whitespace().collect::<Vec<_>>().join(" ")
+            } else {
+                title
+            };
+            let (title, raw_title) = match strip_number_prefix(&title).filter(|_| options.strip_leading_numbers) {
+                Some(stripped) => (stripped.to_string(), Some(title)),
+                None => (title, None),
+            };
+            blocks.push(Block {
+                depth,
+                title,
+                raw_title,
+                body: String::new(),
+                start_line: line_no,
+                end_line: line_no,
+                page_start: None,
+                page_end: None,
+                body_indent: 0,
+            });
+        } else if bodies {
+            body.push(line);
+        }
+    }
 
-            current_depth = depth;
-            current_title = title;
-            current_body = Vec::new();
-            started = true;
-        } else if started {
-            current_body.push(line);
+    if blocks.is_empty() {
+        preamble = finish_body(&body, options);
+    }
+    close_block(&mut blocks, &mut body, line_count, options);
+    Scan { preamble, blocks }
+}
+
+// Replaces tabs in the leading whitespace with spaces up to the next multiple of `tab_width`
+fn expand_leading_tabs(line: &str, tab_width: usize) -> Cow<'_, str> {
+    let indent_len = line.len() - line.trim_start_matches([' ', '\t']).len();
+    if !line[..indent_len].contains('\t') {
+        return Cow::Borrowed(line);
+    }
+    let tab_width = tab_width.max(1);
+    let mut expanded = String::with_capacity(line.len() + tab_width);
+    for c in line[..indent_len].chars() {
+        if c == '\t' {
+            let pad = tab_width - expanded.len() % tab_width;
+            expanded.extend(std::iter::repeat_n(' ', pad));
+        } else {
+            expanded.push(c);
         }
     }
+    expanded.push_str(&line[indent_len..]);
+    Cow::Owned(expanded)
+}
+
+// A `---` fenced block of `key: value` lines at the very top of the document. Returns the pairs and
+// the number of lines the block occupies; an unterminated block is not front matter.
+fn parse_front_matter(markdown: &str) -> (BTreeMap<String, String>, usize) {
+    let mut attributes = BTreeMap::new();
+    let mut lines = markdown.lines();
+    if lines.next().map(str::trim_end) != Some("---") {
+        return (attributes, 0);
+    }
+    for (index, line) in lines.enumerate() {
+        let line = line.trim();
+        if line == "---" || line == "..." {
+            return (attributes, index + 2);
+        }
+        if let Some((key, value)) = line.split_once(':') {
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+                .unwrap_or(value);
+            attributes.insert(key.trim().to_string(), value.to_string());
+        }
+    }
+    (BTreeMap::new(), 0)
+}
+
+// Finalizes the most recent block, which owns every line up to and including `end_line`
+fn close_block(blocks: &mut [Block], body: &mut Vec<Cow<str>>, end_line: usize, options: &ParseOptions) {
+    if let Some(block) = blocks.last_mut() {
+        block.body = finish_body(body, options);
+        block.end_line = end_line;
+    }
+    body.clear();
+}
+
+fn tree_from_blocks(doc_id: &str, source: &str, scan: &Scan, options: &ParseOptions) -> DocumentTree {
+    let doc_title = document_title(&scan.blocks).unwrap_or(doc_id).to_string();
+    let promote = options.promote_single_root && !options.keep_root;
+    let mut root = build_tree(&scan.blocks, promote, &options.id_separator);
+    let several_sections = root.node_id == "0" && root.children.len() > 1;
+    let doc_title = match &options.root_title {
+        Some(title) if several_sections => title.clone(),
+        _ => doc_title,
+    };
+    if options.keep_root {
+        root.title = doc_title.clone();
+        root.text = scan.preamble.clone();
+    }
+    if options.synthesize_root_from_doc_id && several_sections {
+        root.title = doc_id.to_string();
+    }
+    assign_byte_ranges(&mut root, &line_starts(source), source.len());
+    let mut tree = DocumentTree::new(doc_id.to_string(), doc_title, root);
+    tree.id_separator = options.id_separator.clone();
+    if !options.keep_root {
+        tree.preamble = scan.preamble.clone();
+    }
+    tree
+}
+
+// Byte offset at which each line starts, matching the line numbering of str::lines
+pub(crate) fn line_starts(source: &str) -> Vec<usize> {
+    let mut starts = vec![0];
+    starts.extend(source.match_indices('\n').map(|(i, _)| i + 1));
+    starts
+}
+
+// Sets each parsed node's byte range, from the start of its heading line to the end of end_line
+fn assign_byte_ranges(node: &mut Node, line_starts: &[usize], len: usize) {
+    if node.start_line > 0 {
+        let offset = |line: usize| line_starts.get(line).copied().unwrap_or(len);
+        node.byte_start = offset(node.start_line - 1);
+        node.byte_end = offset(node.end_line).max(node.byte_start);
+    }
+    for child in node.children.iter_mut() {
+        assign_byte_ranges(child, line_starts, len);
+    }
+}
 
-    if started {
-        blocks.push((
-            current_depth,
-            current_title,
-            current_body.join("\n").trim().to_string(),
-        ));
+// Each heading's depth becomes one more than the number of shallower headings still open above it
+fn normalize_depths(blocks: &mut [Block]) {
+    let mut open: Vec<usize> = Vec::new();
+    for block in blocks.iter_mut() {
+        while open.last().is_some_and(|top| *top >= block.depth) {
+            open.pop();
+        }
+        open.push(block.depth);
+        block.depth = open.len();
     }
+}
 
-    let root = build_tree(&blocks);
-    DocumentTree::new(doc_id.to_string(), doc_title, root)
+// The first heading at the shallowest level present, so documents that start at `##` still get a title
+fn document_title(blocks: &[Block]) -> Option<&str> {
+    let min_depth = blocks.iter().map(|b| b.depth).min()?;
+    blocks
+        .iter()
+        .find(|b| b.depth == min_depth)
+        .map(|b| b.title.as_str())
 }
 
-fn parse_heading(line: &str) -> Option<(usize, String)> {
+// With `require_space` the `#` run must be followed by a space or tab, as CommonMark requires,
+// so `#hashtag` stays text; otherwise `#Heading` is accepted as a heading
+fn parse_heading(line: &str, require_space: bool) -> Option<(usize, String)> {
+    // Up to three leading spaces are allowed; four or more make the line indented code
+    let indent = line.len() - line.trim_start_matches(' ').len();
+    if indent > 3 {
+        return None;
+    }
+    let line = &line[indent..];
     if !line.starts_with('#') {
         return None;
     }
     let depth = line.chars().take_while(|c| *c == '#').count();
-    let title = line[depth..].trim().to_string();
+    if require_space && !line[depth..].starts_with([' ', '\t']) {
+        return None;
+    }
+    let mut title = line[depth..].trim();
+    // An optional closing sequence (`## Goals ##`) is not part of the title. Content that is only
+    // hashes (`## ##`) is a closing sequence on an empty heading, handled like a bare `##`.
+    if title.chars().all(|c| c == '#') {
+        title = "";
+    } else if let Some(run) = closing_hash_run(title) {
+        title = title[..title.len() - run].trim_end();
+    }
+    if title.is_empty() {
+        return None;
+    }
+    Some((depth, title.to_string()))
+}
+
+// Length of a whitespace-separated trailing run of `#`, as in `Title ##`; `C#` has none
+fn closing_hash_run(title: &str) -> Option<usize> {
+    let without = title.trim_end_matches('#');
+    let run = title.len() - without.len();
+    if run == 0 || !without.ends_with(char::is_whitespace) {
+        return None;
+    }
+    Some(run)
+}
+
+// The title after a leading `\d+(\.\d+)*` number and its whitespace, e.g. `2.1 Experiment`; a
+// trailing dot (`3. Results`) is accepted too. None when there is no such prefix or nothing after it.
+fn strip_number_prefix(title: &str) -> Option<&str> {
+    let bytes = title.as_bytes();
+    let mut end = 0;
+    loop {
+        let digits = bytes[end..].iter().take_while(|b| b.is_ascii_digit()).count();
+        if digits == 0 {
+            return None;
+        }
+        end += digits;
+        if bytes.get(end) == Some(&b'.') && bytes.get(end + 1).is_some_and(u8::is_ascii_digit) {
+            end += 1;
+        } else {
+            break;
+        }
+    }
+    let rest = title[end..].strip_prefix('.').unwrap_or(&title[end..]);
+    let stripped = rest.trim_start();
+    (stripped.len() < rest.len() && !stripped.is_empty()).then_some(stripped)
+}
+
+fn parse_bold_heading(line: &str) -> Option<String> {
+    let line = line.trim();
+    let inner = ["**", "__"].iter().find_map(|marker| {
+        line.strip_prefix(marker)?
+            .strip_suffix(marker)
+            .filter(|inner| !inner.contains(marker))
+    })?;
+    let title = inner.trim();
     if title.is_empty() {
         return None;
     }
-    Some((depth, title))
+    Some(title.to_string())
 }
 
-fn build_tree(blocks: &[(usize, String, String)]) -> Node {
-    let mut root = Node::new("0".to_string(), "root".to_string(), 0, String::new());
-    let mut node_stack: Vec<Node> = vec![root.clone()];
-    let mut depth_counters = vec![0usize; 10];
+// Sets each block's page span from the page markers in the source. Blocks before the first
+// marker get no page.
+fn assign_pages(markdown: &str, blocks: &mut [Block]) {
+    let mut page = None;
+    // The page each line is on, and whether the line is content rather than a marker or blank
+    let lines: Vec<(Option<usize>, bool)> = markdown
+        .lines()
+        .map(|line| match page_marker(line) {
+            Some(number) => {
+                page = Some(number);
+                (page, false)
+            }
+            None => (page, !line.trim().is_empty()),
+        })
+        .collect();
+    for block in blocks.iter_mut() {
+        let start = block.start_line - 1;
+        let span = &lines[start..block.end_line.max(block.start_line)];
+        block.page_start = lines[start].0;
+        block.page_end = span.iter().rev().find(|(_, content)| *content).map_or(block.page_start, |(p, _)| *p);
+    }
+}
 
-    for (depth, title, body) in blocks {
-        let depth = *depth;
+// The page number of a `<!-- page: 12 -->` line; `page 12` and any letter case are accepted too
+fn page_marker(line: &str) -> Option<usize> {
+    let inner = line.trim().strip_prefix("<!--")?.strip_suffix("-->")?.trim();
+    let (word, number) = inner.split_at_checked(4)?;
+    if !word.eq_ignore_ascii_case("page") {
+        return None;
+    }
+    let number = number.trim_start();
+    number.strip_prefix(':').unwrap_or(number).trim().parse().ok()
+}
 
-        depth_counters[depth] += 1;
-        for i in (depth + 1)..10 {
-            depth_counters[i] = 0;
+// Levels of nested list items that become nodes under list_items_as_nodes
+const LIST_ITEM_LEVELS: usize = 2;
+
+// A source line as (1-based line number, text with leading tabs expanded)
+type SourceLine<'a> = (usize, Cow<'a, str>);
+
+// One list item: the text after its marker and the lines after that up to the next sibling
+struct ListItem<'a> {
+    title: String,
+    line_no: usize,
+    lines: Vec<SourceLine<'a>>,
+    // Columns stripped from `lines` so far, counting enclosing items' markers too
+    indent: usize,
+}
+
+// Re-reads the body lines of every block and, where they hold a list, gives each item its own
+// block. The heading keeps the text before the list, and its end_line moves up to just before the
+// first item, as it would for a subsection heading.
+fn split_list_items(markdown: &str, blocks: Vec<Block>, options: &ParseOptions, bodies: bool) -> Vec<Block> {
+    let source: Vec<&str> = markdown.lines().collect();
+    let mut out = Vec::with_capacity(blocks.len());
+    for mut block in blocks {
+        let lines = (block.start_line..block.end_line)
+            .map(|i| (i + 1, expand_leading_tabs(source[i], options.tab_width)))
+            .collect();
+        let (lead, items) = split_list(lines, 0);
+        let Some(first) = items.first() else {
+            out.push(block);
+            continue;
+        };
+        if bodies {
+            block.body = finish_body(&lead.into_iter().map(|(_, l)| l).collect::<Vec<_>>(), options);
+        }
+        block.end_line = first.line_no - 1;
+        let depth = block.depth + 1;
+        out.push(block);
+        push_item_blocks(items, depth, LIST_ITEM_LEVELS, options, bodies, &mut out);
+    }
+    out
+}
+
+fn push_item_blocks(
+    items: Vec<ListItem>,
+    depth: usize,
+    levels: usize,
+    options: &ParseOptions,
+    bodies: bool,
+    out: &mut Vec<Block>,
+) {
+    for item in items {
+        let end_line = item.lines.last().map_or(item.line_no, |(n, _)| *n);
+        let (own, nested) = if levels > 1 {
+            split_list(item.lines, item.indent)
+        } else {
+            (item.lines, Vec::new())
+        };
+        let title = if options.collapse_title_whitespace {
+            item.title.split_whitespace().collect::<Vec<_>>().join(" ")
+        } else {
+            item.title
+        };
+        let body = if bodies {
+            finish_body(&own.into_iter().map(|(_, l)| l).collect::<Vec<_>>(), options)
+        } else {
+            String::new()
+        };
+        out.push(Block {
+            depth,
+            title,
+            raw_title: None,
+            body,
+            start_line: item.line_no,
+            end_line: nested.first().map_or(end_line, |n| n.line_no - 1),
+            page_start: None,
+            page_end: None,
+            body_indent: item.indent,
+        });
+        push_item_blocks(nested, depth + 1, levels - 1, options, bodies, out);
+    }
+}
+
+// Splits lines into the text before the first list item and the items. The first marker's
+// indent sets the list's level: markers at or left of it start sibling items, and every other
+// line after the first item belongs to the item above it, so text following the list ends up in
+// the last item and each item's lines stay contiguous. Item lines lose the marker's content indent,
+// which adds to the `indent` already stripped from `lines`.
+fn split_list(lines: Vec<SourceLine>, indent: usize) -> (Vec<SourceLine>, Vec<ListItem>) {
+    let mut lead = Vec::new();
+    let mut items: Vec<ListItem> = Vec::new();
+    let mut level: Option<(usize, usize)> = None;
+    for (line_no, line) in lines {
+        let marker = list_marker(&line);
+        let starts_item = match (level, marker) {
+            (_, None) => false,
+            (None, Some(_)) => true,
+            (Some((level_indent, _)), Some((indent, _))) => indent <= level_indent,
+        };
+        if let Some((_, content)) = marker.filter(|_| starts_item) {
+            level = level.or(marker);
+            items.push(ListItem {
+                title: line[content..].trim().to_string(),
+                line_no,
+                lines: Vec::new(),
+                indent: indent + content,
+            });
+        } else if level.is_some() {
+            let item = items.last_mut().expect("level is set with the first item");
+            let content = item.indent - indent;
+            item.lines.push((line_no, strip_indent(line, content)));
+        } else {
+            lead.push((line_no, line));
+        }
+    }
+    (lead, items)
+}
+
+// (indent, content offset) of a `- `, `* `, `+ `, `1. ` or `1) ` list item line with text after
+// the marker. Thematic breaks such as `* * *` are not items.
+fn list_marker(line: &str) -> Option<(usize, usize)> {
+    let indent = line.len() - line.trim_start_matches(' ').len();
+    if indent > 3 {
+        return None;
+    }
+    let rest = &line[indent..];
+    let marker = if rest.starts_with(['-', '*', '+']) {
+        1
+    } else {
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        if digits == 0 || digits > 9 || !rest[digits..].starts_with(['.', ')']) {
+            return None;
         }
+        digits + 1
+    };
+    if !rest[marker..].starts_with([' ', '\t']) || rest[marker..].trim().is_empty() {
+        return None;
+    }
+    let compact: Vec<char> = rest.chars().filter(|c| !c.is_whitespace()).collect();
+    if compact.len() >= 3 && compact.iter().all(|c| *c == compact[0]) && matches!(compact[0], '-' | '*') {
+        return None;
+    }
+    Some((indent, indent + marker + 1))
+}
+
+fn build_tree(blocks: &[Block], promote_single_root: bool, id_separator: &str) -> Node {
+    let root = Node::new("0".to_string(), "root".to_string(), 0, String::new());
+    let mut node_stack: Vec<Node> = vec![root];
+    // One counter per level, grown on demand so any depth a detector reports is fine
+    let mut depth_counters: Vec<usize> = Vec::new();
+
+    for block in blocks {
+        let depth = block.depth;
+
+        depth_counters.resize(depth + 1, 0);
+        depth_counters[depth] += 1;
 
         let node_id = depth_counters[1..=depth]
             .iter()
             .map(|c| c.to_string())
             .collect::<Vec<_>>()
-            .join(".");
+            .join(id_separator);
 
-        let node = Node::new(node_id, title.clone(), depth, body.clone());
+        let mut node = Node::new(node_id, block.title.clone(), depth, block.body.clone());
+        node.raw_title = block.raw_title.clone();
+        node.page_start = block.page_start;
+        node.page_end = block.page_end;
+        node.start_line = block.start_line;
+        node.end_line = block.end_line;
 
         while node_stack.len() > 1 {
             let top_depth = node_stack.last().unwrap().depth;
@@ -98,7 +988,7 @@ fn build_tree(blocks: &[(usize, String, String)]) -> Node {
     let mut final_root = node_stack.pop().unwrap();
 
     // Promote to root if there's only one top-level section
-    if final_root.node_id == "0" && final_root.children.len() == 1 {
+    if promote_single_root && final_root.node_id == "0" && final_root.children.len() == 1 {
         final_root.children.remove(0)
     } else {
         final_root
@@ -163,6 +1053,112 @@ Experiment details.
         assert_eq!(tree.all_node_ids().len(), 5);
     }
 
+    #[test]
+    fn test_parse_multi_splits_on_delimiter() {
+        let text = "# First Doc\nOne.\n===DOC===\n\n===DOC===\n---\nid: second\n---\n# Second Doc\n## Part\nTwo.";
+        let trees = parse_multi(text, "===DOC===");
+        assert_eq!(trees.len(), 2);
+        assert_eq!((trees[0].doc_id.as_str(), trees[0].title.as_str()), ("0", "First Doc"));
+        assert_eq!((trees[1].doc_id.as_str(), trees[1].title.as_str()), ("second", "Second Doc"));
+        assert_eq!(trees[1].find_node("1.1").unwrap().text, "Two.");
+    }
+
+    #[test]
+    fn test_parse_multi_default_delimiter_keeps_front_matter() {
+        let text = "---\nid: alpha\ntitle: A\n---\n# Alpha\nOne.\n---\nid: beta\n---\n# Beta\nTwo.\n---\n# Gamma\nNote: three.\n---\n---\nid: delta\n---\n# Delta";
+        let trees = parse_multi(text, "---");
+        let ids: Vec<&str> = trees.iter().map(|t| t.doc_id.as_str()).collect();
+        assert_eq!(ids, vec!["alpha", "beta", "2", "delta"]);
+        assert_eq!(trees[0].attribute("title"), Some("A"));
+        assert_eq!(trees[0].find_node("1").unwrap().text, "One.");
+        assert_eq!(trees[1].title, "Beta");
+        assert_eq!(trees[2].find_node("1").unwrap().text, "Note: three.");
+        assert_eq!(trees[3].title, "Delta");
+    }
+
+    #[test]
+    fn test_parse_files_keeps_input_order() {
+        let dir = std::env::temp_dir().join(format!("pageindex-parse-files-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut paths = Vec::new();
+        for i in 0..6 {
+            let path = dir.join(format!("doc{}.md", i));
+            std::fs::write(&path, format!("# Doc {}\nBody {}.", i, i)).unwrap();
+            paths.push(path);
+        }
+        paths.insert(3, dir.join("missing.md"));
+        let results = parse_files(&paths);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(results.len(), 7);
+        assert!(matches!(results[3], Err(PageIndexError::Io(_))));
+        let titles: Vec<String> = results.iter().flatten().map(|t| format!("{}:{}", t.doc_id, t.title)).collect();
+        assert_eq!(titles, vec!["doc0:Doc 0", "doc1:Doc 1", "doc2:Doc 2", "doc3:Doc 3", "doc4:Doc 4", "doc5:Doc 5"]);
+    }
+
+    #[test]
+    fn test_root_title_used_for_multiple_top_level_sections() {
+        let options = ParseOptions::new().root_title(Some("Field Guide"));
+        let tree = parse_markdown_with_options("doc1", SAMPLE, &options);
+        assert_eq!(tree.title, "Field Guide");
+        let single = parse_markdown_with_options("doc1", "# Only\nText.", &options);
+        assert_eq!(single.title, "Only");
+    }
+
+    #[test]
+    fn test_synthesize_root_from_doc_id_names_node_zero() {
+        let options = ParseOptions::new().synthesize_root_from_doc_id(true);
+        let tree = parse_markdown_with_options("handbook", SAMPLE, &options);
+        assert_eq!(tree.find_node("0").unwrap().title, "handbook");
+        assert_eq!(tree.find_node("0").unwrap().depth, 0);
+        assert_eq!(parse_markdown("handbook", SAMPLE).root.title, "root");
+        let single = parse_markdown_with_options("handbook", "# Only\nText.", &options);
+        assert_eq!(single.root.title, "Only");
+    }
+
+    #[test]
+    fn test_heading_without_space_accepted_by_default() {
+        let tree = parse_markdown("doc", "#Heading\nText.\n##Sub\nMore.");
+        assert_eq!(tree.titles(), vec!["Heading", "Sub"]);
+        assert_eq!(tree.find_node("1.1").unwrap().depth, 2);
+    }
+
+    #[test]
+    fn test_require_space_after_hash_rejects_tight_headings() {
+        let md = "# Tags\n#rust and #python\n#\tTabbed\nBody.";
+        let options = ParseOptions::new().require_space_after_hash(true);
+        let tree = parse_markdown_with_options("doc", md, &options);
+        assert_eq!(tree.titles(), vec!["Tags", "Tabbed"]);
+        assert_eq!(tree.find_node("1").unwrap().text, "#rust and #python");
+    }
+
+    #[test]
+    fn test_titles_in_document_order() {
+        let tree = parse_markdown("doc1", SAMPLE);
+        assert_eq!(tree.titles(), vec!["Introduction", "Background", "Goals", "Methods", "Experiment"]);
+    }
+
+    #[test]
+    fn test_numbered_markdown_prefixes_ids() {
+        let tree = parse_markdown("doc1", SAMPLE);
+        let numbered = tree.to_numbered_markdown();
+        assert!(numbered.starts_with("# 1 Introduction\n\nIntroductory text."));
+        assert!(numbered.contains("\n## 2.1 Experiment\n\nExperiment details.\n"));
+        let reparsed = parse_markdown("doc1", &numbered);
+        assert_eq!(reparsed.all_node_ids(), tree.all_node_ids());
+    }
+
+    // What PageIndex.__len__ and __contains__ report
+    #[test]
+    fn test_len_and_membership_agree_with_ids() {
+        let tree = parse_markdown("doc1", SAMPLE);
+        let ids = tree.all_node_ids();
+        assert_eq!(tree.node_count(), ids.len());
+        assert!(ids.iter().all(|id| tree.contains(id)));
+        assert!(tree.contains("1.1"));
+        assert!(!tree.contains("1.9"));
+    }
+
     #[test]
     fn test_empty_markdown_produces_root() {
         let tree = parse_markdown("empty", "");
@@ -177,4 +1173,390 @@ Experiment details.
         let node = tree.find_node("1").unwrap();
         assert_eq!(node.text, "Some text.");
     }
+
+    #[test]
+    fn test_doc_title_from_first_h2_when_no_h1() {
+        let md = "## Overview\nOverview text.\n\n### Details\nDetail text.\n\n## Usage\nUsage text.";
+        let tree = parse_markdown("doc2", md);
+        assert_eq!(tree.title, "Overview");
+    }
+
+    #[test]
+    fn test_skipped_level_keeps_gap_by_default() {
+        let md = "# Guide\nIntro.\n\n### Install\nSteps.";
+        let tree = parse_markdown("skip", md);
+        assert!(tree.find_node("1.0.1").is_some());
+    }
+
+    #[test]
+    fn test_normalized_skipped_level_becomes_direct_child() {
+        let md = "# Guide\nIntro.\n\n### Install\nSteps.\n\n# Reference\nRef.";
+        let tree = parse_markdown_normalized("skip", md);
+        let guide = tree.find_node("1").unwrap();
+        assert_eq!(guide.children.len(), 1);
+        let install = &guide.children[0];
+        assert_eq!(install.node_id, "1.1");
+        assert_eq!(install.title, "Install");
+        assert_eq!(install.depth, 2);
+        assert!(tree.find_node("2").is_some());
+    }
+
+    #[test]
+    fn test_flatten_beyond_depth_clamps_deep_headings_to_siblings() {
+        let md = "# A\na\n## B\nb\n### C\nc\n### D\nd\n## E\ne";
+        let options = ParseOptions::new().flatten_beyond_depth(Some(2));
+        let tree = parse_markdown_with_options("flat", md, &options);
+        let a = tree.find_node("1").unwrap();
+        let titles: Vec<&str> = a.children.iter().map(|c| c.title.as_str()).collect();
+        assert_eq!(titles, vec!["B", "C", "D", "E"]);
+        assert!(a.children.iter().all(|c| c.depth == 2 && c.children.is_empty()));
+        assert_eq!(tree.find_node("1.3").unwrap().title, "D");
+        assert_eq!(tree.find_node("1.3").unwrap().text, "d");
+    }
+
+    #[test]
+    fn test_default_options_match_parse_markdown() {
+        let with_defaults = parse_markdown_with_options("doc1", SAMPLE, &ParseOptions::default());
+        assert_eq!(with_defaults.to_json(), parse_markdown("doc1", SAMPLE).to_json());
+    }
+
+    #[test]
+    fn test_root_promotion_flag_changes_tree_shape() {
+        let md = "# Only Section\nSome text.\n\n## Detail\nMore text.";
+        let promoted = parse_markdown_with_options("single", md, &ParseOptions::new());
+        assert_eq!(promoted.root.node_id, "1");
+
+        let kept = parse_markdown_with_options("single", md, &ParseOptions::new().promote_single_root(false));
+        assert_eq!(kept.root.node_id, "0");
+        assert_eq!(kept.root.children.len(), 1);
+        assert_eq!(kept.all_node_ids(), promoted.all_node_ids());
+    }
+
+    #[test]
+    fn test_max_heading_depth_folds_deep_headings_into_parent() {
+        let md = "# Guide\nIntro.\n\n## Setup\nSetup text.\n\n#### Linux\nUse apt.\n\n## Usage\nRun it.";
+        let options = ParseOptions::new().max_heading_depth(Some(2));
+        let tree = parse_markdown_with_options("deep", md, &options);
+        assert_eq!(tree.all_node_ids(), vec!["1", "1.1", "1.2"]);
+        let setup = tree.find_node("1.1").unwrap();
+        assert_eq!(setup.text, "Setup text.\n\n#### Linux\nUse apt.");
+        assert!(setup.children.is_empty());
+    }
+
+    #[test]
+    fn test_heading_allows_up_to_three_leading_spaces() {
+        let md = "# Top\n\n ## One Space\na\n\n   ## Three Spaces\nb\n\n    ## Four Spaces\nc";
+        let tree = parse_markdown("indent", md);
+        assert_eq!(tree.find_node("1.1").unwrap().title, "One Space");
+        let three = tree.find_node("1.2").unwrap();
+        assert_eq!(three.title, "Three Spaces");
+        assert!(tree.find_node("1.3").is_none());
+        assert!(three.text.contains("    ## Four Spaces"));
+    }
+
+    #[test]
+    fn test_bold_line_becomes_heading_when_enabled() {
+        let md = "# Report\nIntro.\n\n**Results**\nIt worked, **mostly**.\n\n**Open Questions **\nMany.";
+        let options = ParseOptions::new().bold_heading_depth(Some(2));
+        let tree = parse_markdown_with_options("bold", md, &options);
+        let results = tree.find_node("1.1").unwrap();
+        assert_eq!(results.title, "Results");
+        assert_eq!(results.depth, 2);
+        assert_eq!(results.text, "It worked, **mostly**.");
+        assert_eq!(tree.find_node("1.2").unwrap().title, "Open Questions");
+    }
+
+    #[test]
+    fn test_bold_line_stays_body_by_default() {
+        let md = "# Report\nIntro.\n\n**Results**\nIt worked.";
+        let tree = parse_markdown("bold", md);
+        assert_eq!(tree.all_node_ids(), vec!["1"]);
+        assert!(tree.find_node("1").unwrap().text.contains("**Results**"));
+    }
+
+    #[test]
+    fn test_single_section_keeps_synthetic_root_without_promotion() {
+        let options = ParseOptions::new().promote_single_root(false);
+        let tree = parse_markdown_with_options("single", "# Only Section\nSome text.", &options);
+        assert_eq!(tree.root.node_id, "0");
+        assert_eq!(tree.root.children.len(), 1);
+        assert_eq!(tree.root.children[0].node_id, "1");
+        assert_eq!(tree.title, "Only Section");
+        assert_eq!(tree.all_node_ids(), vec!["1"]);
+    }
+
+    #[test]
+    fn test_line_span_ends_before_next_heading() {
+        let tree = parse_markdown("doc1", SAMPLE);
+        let intro = tree.find_node("1").unwrap();
+        let background_line = SAMPLE.lines().position(|l| l == "## Background").unwrap() + 1;
+        assert_eq!(SAMPLE.lines().nth(intro.start_line - 1), Some("# Introduction"));
+        assert_eq!(intro.end_line, background_line - 1);
+    }
+
+    #[test]
+    fn test_line_spans_serialized_in_json() {
+        let tree = parse_markdown("doc1", SAMPLE);
+        let json = tree.to_json();
+        assert!(json.contains("\"start_line\": 2"));
+        assert!(json.contains("\"end_line\": 4"));
+    }
+
+    #[test]
+    fn test_line_spans_cover_heading_through_body() {
+        let tree = parse_markdown("doc1", SAMPLE);
+        let background = tree.find_node("1.1").unwrap();
+        assert_eq!((background.start_line, background.end_line), (5, 7));
+        let experiment = tree.find_node("2.1").unwrap();
+        assert_eq!((experiment.start_line, experiment.end_line), (14, 15));
+    }
+
+    #[test]
+    fn test_closing_hash_mismatch_reported_when_enabled() {
+        let md = "# Guide #\nIntro.\n\n## Title ####\nBody.\n\n## C#\nSharp.";
+        let options = ParseOptions::new().check_closing_hashes(true);
+        let (_, diagnostics) = parse_markdown_with_diagnostics("hashes", md, &options);
+        assert_eq!(
+            diagnostics,
+            vec![Diagnostic::ClosingHashMismatch { line: 4, opening: 2, closing: 4 }]
+        );
+        assert_eq!(diagnostics[0].to_string(), "line 4: heading opens with 2 '#' but closes with 4");
+    }
+
+    #[test]
+    fn test_list_items_become_child_nodes() {
+        let md = "# Checklist\nBefore you ship:\n\n- Tests pass\n- Docs updated\n  with examples\n- Changelog\n\nThat's all.";
+        let options = ParseOptions::new().list_items_as_nodes(true);
+        let tree = parse_markdown_with_options("doc1", md, &options);
+        let root = tree.find_node("1").unwrap();
+        assert_eq!(root.text, "Before you ship:");
+        assert_eq!(root.end_line, 3);
+        let items: Vec<_> = root.children.iter().map(|c| (c.node_id.as_str(), c.title.as_str(), c.depth)).collect();
+        assert_eq!(items, vec![("1.1", "Tests pass", 2), ("1.2", "Docs updated", 2), ("1.3", "Changelog", 2)]);
+        assert_eq!(root.children[1].text, "with examples");
+        assert_eq!(root.children[2].text, "That's all.");
+        assert_eq!((root.children[2].start_line, root.children[2].end_line), (7, 9));
+        assert_eq!(parse_markdown("doc1", md).node_count(), 1);
+    }
+
+    #[test]
+    fn test_nested_list_items_two_levels_deep() {
+        let md = "# Plan\n1. Build\n   - compile\n     * debug\n   - link\n2. Ship";
+        let options = ParseOptions::new().list_items_as_nodes(true);
+        let tree = parse_markdown_with_options("doc1", md, &options);
+        assert_eq!(tree.titles(), vec!["Plan", "Build", "compile", "link", "Ship"]);
+        assert_eq!(tree.find_node("1.1.1").unwrap().text, "* debug");
+        assert_eq!(tree.find_node("1.1").unwrap().end_line, 2);
+        assert!(tree.find_node("1.1").unwrap().text.is_empty());
+    }
+
+    #[test]
+    fn test_page_markers_assign_page_spans() {
+        let md = "# Report\nCover.\n<!-- page: 2 -->\n## Summary\nFirst half.\n\n<!-- Page 3 -->\nSecond half.\n\n## Results\nNumbers.\n<!-- page: 4 -->\n\n## Appendix\nExtra.";
+        let options = ParseOptions::new().page_markers(true);
+        let tree = parse_markdown_with_options("doc1", md, &options);
+        let pages = |id: &str| {
+            let node = tree.find_node(id).unwrap();
+            (node.page_start, node.page_end)
+        };
+        assert_eq!(pages("1"), (None, None));
+        assert_eq!(pages("1.1"), (Some(2), Some(3)));
+        assert_eq!(pages("1.2"), (Some(3), Some(3)));
+        assert_eq!(pages("1.3"), (Some(4), Some(4)));
+        assert_eq!(tree.find_node("1.1").unwrap().text, "First half.\n\nSecond half.");
+        assert_eq!(tree.find_node("1").unwrap().text, "Cover.");
+        let plain = parse_markdown("doc1", md);
+        assert!(plain.find_node("1.1").unwrap().text.contains("<!-- Page 3 -->"));
+        assert_eq!(plain.find_node("1.1").unwrap().page_start, None);
+    }
+
+    #[test]
+    fn test_closing_hashes_stripped_from_titles() {
+        let md = "## Goals ##\nAims.\n## C# \nSharp.\n## Issue #42 ###\nBug.";
+        let tree = parse_markdown("hashes", md);
+        assert_eq!(tree.titles(), vec!["Goals", "C#", "Issue #42"]);
+        assert_eq!(parse_heading("## ##", false), None);
+        assert_eq!(parse_heading("#  #", false), None);
+        assert_eq!(parse_heading("## ##", false), parse_heading("##", false));
+        assert_eq!(parse_markdown("hashes", "# Doc\n## ##\n#  #\nBody.").titles(), vec!["Doc"]);
+    }
+
+    #[test]
+    fn test_closing_hash_check_off_by_default() {
+        let md = "## Title ####\nBody.";
+        let (_, diagnostics) = parse_markdown_with_diagnostics("hashes", md, &ParseOptions::default());
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_front_matter_populates_attributes() {
+        let md = "---\nauthor: Ada Lovelace\nsource: \"https://example.com\"\n# not a heading\n---\n# Notes\nBody.";
+        let tree = parse_markdown_with_options("fm", md, &ParseOptions::new().front_matter(true));
+        assert_eq!(tree.attribute("author"), Some("Ada Lovelace"));
+        assert_eq!(tree.attribute("source"), Some("https://example.com"));
+        assert_eq!(tree.all_node_ids(), vec!["1"]);
+        assert_eq!(tree.title, "Notes");
+        assert_eq!(tree.find_node("1").unwrap().start_line, 6);
+    }
+
+    #[test]
+    fn test_front_matter_ignored_by_default() {
+        let md = "---\nauthor: Ada\n---\n# Notes\nBody.";
+        let tree = parse_markdown("fm", md);
+        assert!(tree.attributes.is_empty());
+    }
+
+    #[test]
+    fn test_parse_as_pages_flattens_to_two_levels() {
+        let md = "# Page One\np1\n## Section A\na\n### Deep A1\na1\n# Page Two\np2\n### Deep B\nb";
+        let tree = parse_as_pages("pages", md);
+        assert_eq!(tree.all_node_ids(), vec!["1", "1.1", "1.2", "2", "2.1"]);
+        assert!(tree.iter().all(|n| n.depth <= 2));
+        let page_one = tree.find_node("1").unwrap();
+        let titles: Vec<&str> = page_one.children.iter().map(|c| c.title.as_str()).collect();
+        assert_eq!(titles, vec!["Section A", "Deep A1"]);
+        assert!(page_one.children.iter().all(|c| c.children.is_empty()));
+        assert_eq!(tree.find_node("2.1").unwrap().title, "Deep B");
+    }
+
+    #[test]
+    fn test_tab_indented_doc_matches_space_expanded() {
+        let tabbed = "#\tIntro\n\tcode line\n  \t# not a heading\n\n## Details\n\t- item";
+        let spaced = "#\tIntro\n    code line\n    # not a heading\n\n## Details\n    - item";
+        let a = parse_markdown("doc", tabbed);
+        let b = parse_markdown("doc", spaced);
+        // Byte offsets differ with the source, everything else must match
+        let fields = |tree: &DocumentTree| {
+            tree.iter()
+                .map(|n| (n.node_id.clone(), n.title.clone(), n.text.clone(), n.start_line, n.end_line))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(fields(&a), fields(&b));
+        assert_eq!(a.find_node("1").unwrap().title, "Intro");
+        assert_eq!(a.all_node_ids(), vec!["1", "1.1"]);
+    }
+
+    #[test]
+    fn test_tab_width_two_allows_tab_before_heading() {
+        let md = "\t# Title\nBody.";
+        assert!(parse_markdown("doc", md).find_node("1").is_none());
+        let tree = parse_markdown_with_options("doc", md, &ParseOptions::new().tab_width(2));
+        assert_eq!(tree.find_node("1").unwrap().title, "Title");
+    }
+
+    #[test]
+    fn test_strip_leading_numbers_cleans_titles() {
+        let md = "# 2 Methods\n## 2.1 Experiment\n## 3. Results\n## 1.5x Speedup\n## 2024";
+        let options = ParseOptions::new().strip_leading_numbers(true);
+        let tree = parse_markdown_with_options("doc", md, &options);
+        assert_eq!(tree.titles(), vec!["Methods", "Experiment", "Results", "1.5x Speedup", "2024"]);
+        let experiment = tree.find_node("1.1").unwrap();
+        assert_eq!(experiment.raw_title.as_deref(), Some("2.1 Experiment"));
+        assert!(tree.find_node("1.3").unwrap().raw_title.is_none());
+        assert_eq!(parse_markdown("doc", md).find_node("1.1").unwrap().title, "2.1 Experiment");
+    }
+
+    fn section_detector(line: &str) -> Option<(usize, String)> {
+        let rest = line.strip_prefix("SECTION ")?;
+        let (number, title) = rest.split_once(':')?;
+        let depth = number.split('.').count();
+        number.split('.').all(|p| p.parse::<usize>().is_ok()).then(|| (depth, title.trim().to_string()))
+    }
+
+    #[test]
+    fn test_parse_with_detector_uses_custom_headings() {
+        let text = "SECTION 1: Scope\nWhat is covered.\nSECTION 1.1: Limits\nEdge cases.\nSECTION 2: Terms\n# not a heading";
+        let tree = parse_with_detector("contract", text, section_detector);
+        assert_eq!(tree.all_node_ids(), vec!["1", "1.1", "2"]);
+        assert_eq!(tree.titles(), vec!["Scope", "Limits", "Terms"]);
+        assert_eq!(tree.find_node("1").unwrap().text, "What is covered.");
+        assert_eq!(tree.find_node("2").unwrap().text, "# not a heading");
+    }
+
+    #[test]
+    fn test_headings_deeper_than_nine_levels() {
+        let tree = parse_with_detector("deep", "12 Deep\nBody.", |line| {
+            let (depth, title) = line.split_once(' ')?;
+            Some((depth.parse().ok()?, title.to_string()))
+        });
+        assert_eq!(tree.find_node("0.0.0.0.0.0.0.0.0.0.0.1").unwrap().title, "Deep");
+    }
+
+    #[test]
+    fn test_id_separator_used_for_ids_and_breadcrumbs() {
+        let options = ParseOptions::new().id_separator("-");
+        let mut tree = parse_markdown_with_options("doc1", SAMPLE, &options);
+        assert_eq!(tree.all_node_ids(), vec!["1", "1-1", "1-2", "2", "2-1"]);
+        let result = crate::traversal::get_node(&tree, "2-1").unwrap();
+        assert_eq!(result.breadcrumb, vec!["Methods", "Experiment"]);
+        assert!(tree.verify_breadcrumbs());
+        assert!(tree.delete_node("1-1"));
+        assert_eq!(tree.all_node_ids(), vec!["1", "1-1", "2", "2-1"]);
+        assert!(tree.verify_id_consistency());
+    }
+
+    #[test]
+    fn test_collapse_blank_lines_leaves_one_between_paragraphs() {
+        let md = "# Notes\nFirst.\n\n\n\nSecond.\n  \n\nThird.";
+        let tree = parse_markdown_with_options("doc", md, &ParseOptions::new().collapse_blank_lines(true));
+        assert_eq!(tree.find_node("1").unwrap().text, "First.\n\nSecond.\n\nThird.");
+        assert_eq!(parse_markdown("doc", md).find_node("1").unwrap().text, "First.\n\n\n\nSecond.\n  \n\nThird.");
+    }
+
+    #[test]
+    fn test_dedent_strips_common_indentation() {
+        let md = "# Notes\n  - one\n    - nested\n\n  - two";
+        let tree = parse_markdown_with_options("doc", md, &ParseOptions::new().dedent(true));
+        assert_eq!(tree.find_node("1").unwrap().text, "- one\n  - nested\n\n- two");
+        assert_eq!(parse_markdown("doc", md).find_node("1").unwrap().text, "- one\n    - nested\n\n  - two");
+    }
+
+    #[test]
+    fn test_dedent_ignores_page_marker_indent() {
+        let md = "# Notes\n    first\n<!-- page: 2 -->\n      second";
+        let options = ParseOptions::new().dedent(true).page_markers(true);
+        let tree = parse_markdown_with_options("doc", md, &options);
+        assert_eq!(tree.find_node("1").unwrap().text, "first\n  second");
+        assert_eq!(tree.find_node("1").unwrap().page_end, Some(2));
+    }
+
+    #[test]
+    fn test_title_whitespace_runs_collapse_by_default() {
+        let md = "# Goals    and \t Objectives\nKeep   these   spaces.";
+        let tree = parse_markdown("doc", md);
+        assert_eq!(tree.find_node("1").unwrap().title, "Goals and Objectives");
+        assert_eq!(tree.find_node("1").unwrap().text, "Keep   these   spaces.");
+        let options = ParseOptions::new().collapse_title_whitespace(false);
+        let raw = parse_markdown_with_options("doc", md, &options);
+        assert_eq!(raw.find_node("1").unwrap().title, "Goals    and \t Objectives");
+    }
+
+    #[test]
+    fn test_keep_root_exposes_title_and_preamble() {
+        let md = "Written for new staff.\n\n# Onboarding\nWelcome.";
+        let tree = parse_markdown_with_options("guide", md, &ParseOptions::new().keep_root(true));
+        let root = tree.find_node("0").unwrap();
+        assert_eq!(root.title, "Onboarding");
+        assert_eq!(root.text, "Written for new staff.");
+        assert_eq!(root.children.len(), 1);
+        assert_eq!(tree.find_node("1").unwrap().text, "Welcome.");
+    }
+
+    #[test]
+    fn test_preamble_kept_off_nodes_without_keep_root() {
+        let md = "Written for new staff.\n\n# Onboarding\nWelcome.\n\n# FAQ\nAsk.";
+        let tree = parse_markdown("guide", md);
+        assert_eq!(tree.root.node_id, "0");
+        assert!(tree.root.text.is_empty());
+        assert_eq!(tree.orphan_text(), vec![("0".to_string(), "Written for new staff.".to_string())]);
+        let kept = parse_markdown_with_options("guide", md, &ParseOptions::new().keep_root(true));
+        assert_eq!(kept.orphan_text(), tree.orphan_text());
+    }
+
+    #[test]
+    fn test_doc_title_skips_deeper_leading_heading() {
+        let md = "### Note\nA note.\n\n## Summary\nSummary text.";
+        let tree = parse_markdown("doc3", md);
+        assert_eq!(tree.title, "Summary");
+    }
 }
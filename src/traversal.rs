@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+
 use crate::tree::{DocumentTree, Node};
 
 #[derive(Debug, Clone)]
@@ -8,45 +10,261 @@ pub struct TraversalResult {
     pub summary: Option<String>,
     pub depth: usize,
     pub breadcrumb: Vec<String>,
+    // Node::slug of the title; not deduplicated, see DocumentTree::unique_slugs
+    pub slug: String,
+    pub start_line: usize,
+    pub end_line: usize,
+    pub page_start: Option<usize>,
+    pub page_end: Option<usize>,
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl TraversalResult {
+    // The breadcrumb joined into one string, e.g. `Methods > Experiment` with sep " > "
+    pub fn breadcrumb_string(&self, sep: &str) -> String {
+        self.breadcrumb.join(sep)
+    }
+
+    // The summary when one is set, otherwise the text
+    pub fn best_content(&self) -> &str {
+        self.summary.as_deref().unwrap_or(&self.text)
+    }
+}
+
+// (start, end, node_id): a byte range of assembled text and the node it came from
+pub type TextSpan = (usize, usize, String);
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TreeDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub modified: Vec<String>,
 }
 
 pub fn get_node(tree: &DocumentTree, node_id: &str) -> Option<TraversalResult> {
     let breadcrumb = build_breadcrumb(tree, node_id);
-    tree.find_node(node_id).map(|node| TraversalResult {
-        node_id: node.node_id.clone(),
-        title: node.title.clone(),
-        text: node.text.clone(),
-        summary: node.summary.clone(),
-        depth: node.depth,
-        breadcrumb,
-    })
+    tree.find_node(node_id)
+        .map(|node| result_for(node, node.text.clone(), breadcrumb))
+}
+
+// get_node with the text cut to its first `max_chars` characters plus `…` when it is longer.
+// Counts chars rather than bytes, so multi-byte text is never split mid-character.
+pub fn get_node_preview(tree: &DocumentTree, node_id: &str, max_chars: usize) -> Option<TraversalResult> {
+    let mut result = get_node(tree, node_id)?;
+    if let Some((cut, _)) = result.text.char_indices().nth(max_chars) {
+        result.text.truncate(cut);
+        result.text.push('…');
+    }
+    Some(result)
 }
 
 pub fn get_node_with_children(tree: &DocumentTree, node_id: &str) -> Option<TraversalResult> {
     let breadcrumb = build_breadcrumb(tree, node_id);
     tree.find_node(node_id).map(|node| {
         let full_text = collect_subtree_text(node);
-        TraversalResult {
-            node_id: node.node_id.clone(),
-            title: node.title.clone(),
-            text: full_text,
-            summary: node.summary.clone(),
-            depth: node.depth,
-            breadcrumb,
-        }
+        let mut result = result_for(node, full_text, breadcrumb);
+        result.end_line = subtree_end_line(node);
+        result
     })
 }
 
+// Like get_node_with_children, plus a table of (start, end, node_id) byte ranges into the text,
+// so a citation offset in the assembled text can be traced back to its section
+pub fn get_node_with_children_mapped(tree: &DocumentTree, node_id: &str) -> Option<(String, Vec<TextSpan>)> {
+    tree.find_node(node_id).map(collect_subtree_text_mapped)
+}
+
+// One prompt block for a single LLM call: document outline, the path down to the node, ancestor
+// text and the node's full subtree text. The target text is always included; ancestors (nearest
+// first) and then the outline are added only while the total stays within `max_tokens`, as
+// measured piece by piece with `tokenizer`. Empty for an unknown node.
+pub fn context_pack(
+    tree: &DocumentTree,
+    node_id: &str,
+    max_tokens: usize,
+    tokenizer: &dyn Fn(&str) -> usize,
+) -> String {
+    let Some(node) = tree.find_node(node_id) else {
+        return String::new();
+    };
+    let target = format!("Section [{}] {}:\n{}", node.node_id, node.title, collect_subtree_text(node));
+    let mut used = tokenizer(&target);
+    let mut fits = |piece: &str| {
+        let cost = tokenizer(piece);
+        if used + cost > max_tokens {
+            return false;
+        }
+        used += cost;
+        true
+    };
+
+    let path = tree.node_path(node_id).unwrap_or_default();
+    let mut ancestors = Vec::new();
+    let breadcrumb = path.iter().map(|n| n.title.as_str()).collect::<Vec<_>>().join(" > ");
+    let breadcrumb = format!("Path: {}", breadcrumb);
+    if fits(&breadcrumb) {
+        for ancestor in path.iter().rev().skip(1).filter(|n| !n.text.is_empty()) {
+            let piece = format!("[{}] {}:\n{}", ancestor.node_id, ancestor.title, ancestor.text);
+            if !fits(&piece) {
+                break;
+            }
+            ancestors.insert(0, piece);
+        }
+        ancestors.insert(0, breadcrumb);
+    }
+    let outline = format!("Document outline:\n{}", get_tree_outline(tree));
+    let mut parts = Vec::new();
+    if fits(&outline) {
+        parts.push(outline);
+    }
+    parts.extend(ancestors);
+    parts.push(target);
+    parts.join("\n\n")
+}
+
+// Several retrieved sections as one prompt, in the order given: each is a `# A > B` breadcrumb
+// header followed by its full subtree text. Ids below a section already in the context, whose
+// text that section carries, are skipped, and a section that arrives after some of its
+// descendants replaces them; unknown ids are skipped too. Sections are added while the total
+// stays within `max_tokens` by estimate_tokens; the first one that does not fit ends the context.
+pub fn assemble_context(tree: &DocumentTree, node_ids: &[String], max_tokens: usize) -> String {
+    // (ids from the root down to the section, rendered section, its token cost)
+    let mut parts: Vec<(Vec<&str>, String, usize)> = Vec::new();
+    let mut used = 0;
+    for node_id in node_ids {
+        let Some(path) = tree.node_path(node_id) else {
+            continue;
+        };
+        let ids: Vec<&str> = path.iter().map(|n| n.node_id.as_str()).collect();
+        let included = |id: &str| parts.iter().any(|(part_ids, _, _)| part_ids.last() == Some(&id));
+        if ids.iter().any(|id| included(id)) {
+            continue;
+        }
+        let node = path[path.len() - 1];
+        let breadcrumb = path.iter().map(|n| n.title.as_str()).collect::<Vec<_>>().join(" > ");
+        let text = collect_subtree_text(node);
+        let part = if text.is_empty() {
+            format!("# {}", breadcrumb)
+        } else {
+            format!("# {}\n\n{}", breadcrumb, text)
+        };
+        let cost = estimate_tokens(&part);
+        let is_descendant = |part_ids: &[&str]| part_ids[..part_ids.len() - 1].contains(&node.node_id.as_str());
+        let freed: usize = parts
+            .iter()
+            .filter(|(part_ids, _, _)| is_descendant(part_ids))
+            .map(|(_, _, cost)| cost)
+            .sum();
+        if used - freed + cost > max_tokens {
+            break;
+        }
+        parts.retain(|(part_ids, _, _)| !is_descendant(part_ids));
+        used = used - freed + cost;
+        parts.push((ids, part, cost));
+    }
+    parts.into_iter().map(|(_, part, _)| part).collect::<Vec<_>>().join("\n\n")
+}
+
+// Rough token count for budgeting without a real tokenizer: one token per four characters
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+// The node plus its nearest siblings, taken alternately before and after it, for as long as each
+// next sibling's text still fits in `max_tokens` alongside the rest. The target is always
+// included. Results are in document order; empty for an unknown node.
+pub fn sibling_window(
+    tree: &DocumentTree,
+    node_id: &str,
+    max_tokens: usize,
+    tokenizer: &dyn Fn(&str) -> usize,
+) -> Vec<TraversalResult> {
+    let Some(path) = tree.node_path(node_id) else {
+        return Vec::new();
+    };
+    let siblings: &[Node] = if path.len() >= 2 {
+        &path[path.len() - 2].children
+    } else if node_id == tree.root.node_id {
+        std::slice::from_ref(&tree.root)
+    } else {
+        tree.top_level()
+    };
+    let Some(target) = siblings.iter().position(|n| n.node_id == node_id) else {
+        return Vec::new();
+    };
+
+    let mut used = tokenizer(&siblings[target].text);
+    let (mut first, mut last) = (target, target);
+    let mut before = true;
+    loop {
+        let next = if before {
+            first.checked_sub(1)
+        } else {
+            Some(last + 1).filter(|i| *i < siblings.len())
+        };
+        let other_side_open = if before { last + 1 < siblings.len() } else { first > 0 };
+        match next {
+            Some(i) => {
+                let cost = tokenizer(&siblings[i].text);
+                if used + cost > max_tokens {
+                    break;
+                }
+                used += cost;
+                if before {
+                    first = i;
+                } else {
+                    last = i;
+                }
+            }
+            None if !other_side_open => break,
+            None => {}
+        }
+        before = !before;
+    }
+
+    let parent_breadcrumb: Vec<String> = path[..path.len() - 1].iter().map(|n| n.title.clone()).collect();
+    siblings[first..=last]
+        .iter()
+        .map(|n| {
+            let mut breadcrumb = parent_breadcrumb.clone();
+            breadcrumb.push(n.title.clone());
+            result_for(n, n.text.clone(), breadcrumb)
+        })
+        .collect()
+}
+
 // Produces a compact outline for LLM consumption, e.g.:
 // [1] Introduction
 //   [1.1] Background
 //   [1.2] Goals
 pub fn get_tree_outline(tree: &DocumentTree) -> String {
     let mut lines = Vec::new();
-    outline_node(&tree.root, &mut lines);
+    outline_node(&tree.root, None, &mut lines);
+    lines.join("\n")
+}
+
+// get_tree_outline limited to nodes at `max_depth` or shallower, as visible_ids selects them.
+// A shown node whose children are hidden ends in ` …` so the reader knows there is more:
+// [1] Introduction …
+// [2] Methods
+pub fn get_tree_outline_collapsed(tree: &DocumentTree, max_depth: usize) -> String {
+    let mut lines = Vec::new();
+    outline_node(&tree.root, Some(max_depth), &mut lines);
     lines.join("\n")
 }
 
+// Deterministic snapshot outline, one `[id] (depth) <hash8> Title` line per node, where hash8 is the
+// first 8 hex digits of the content hash. Changes whenever structure or any section's content does.
+pub fn get_tree_fingerprint_outline(tree: &DocumentTree) -> String {
+    tree.iter()
+        .map(|n| {
+            let hash = format!("{:016x}", n.content_hash());
+            format!("[{}] ({}) <{}> {}", n.node_id, n.depth, &hash[..8], n.title)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 pub fn get_children(tree: &DocumentTree, node_id: &str) -> Vec<(String, String)> {
     tree.find_node(node_id)
         .map(|node| {
@@ -58,42 +276,240 @@ pub fn get_children(tree: &DocumentTree, node_id: &str) -> Vec<(String, String)>
         .unwrap_or_default()
 }
 
+// Full results for each immediate child, saving a get_node round trip per child
+pub fn get_children_detailed(tree: &DocumentTree, node_id: &str) -> Vec<TraversalResult> {
+    let Some(node) = tree.find_node(node_id) else {
+        return Vec::new();
+    };
+    let parent_breadcrumb = build_breadcrumb(tree, node_id);
+    node.children
+        .iter()
+        .map(|child| {
+            let mut breadcrumb = parent_breadcrumb.clone();
+            breadcrumb.push(child.title.clone());
+            result_for(child, child.text.clone(), breadcrumb)
+        })
+        .collect()
+}
+
+// A result for every real node in document order, built in one walk that carries the breadcrumb
+// down instead of resolving each node's path separately
+pub fn all_results(tree: &DocumentTree) -> Vec<TraversalResult> {
+    fn visit(node: &Node, breadcrumb: &mut Vec<String>, out: &mut Vec<TraversalResult>) {
+        breadcrumb.push(node.title.clone());
+        out.push(result_for(node, node.text.clone(), breadcrumb.clone()));
+        for child in &node.children {
+            visit(child, breadcrumb, out);
+        }
+        breadcrumb.pop();
+    }
+    let mut out = Vec::with_capacity(tree.node_count());
+    for node in tree.top_level() {
+        visit(node, &mut Vec::new(), &mut out);
+    }
+    out
+}
+
+// Case-insensitive substring search over titles and text, yielding matches lazily in document order
+pub fn search_iter<'a>(tree: &'a DocumentTree, query: &'a str) -> impl Iterator<Item = TraversalResult> + 'a {
+    let needle = query.to_lowercase();
+    tree.iter()
+        .filter(move |n| n.title.to_lowercase().contains(&needle) || n.text.to_lowercase().contains(&needle))
+        .map(move |n| result_for(n, n.text.clone(), build_breadcrumb(tree, &n.node_id)))
+}
+
+// (node_id, title) for each ancestor and then the node itself, root to node; the synthetic root
+// is left out as in breadcrumbs. Empty for an unknown id.
+pub fn get_path(tree: &DocumentTree, node_id: &str) -> Vec<(String, String)> {
+    tree.node_path(node_id)
+        .unwrap_or_default()
+        .iter()
+        .map(|n| (n.node_id.clone(), n.title.clone()))
+        .collect()
+}
+
+// Resolves a path of section titles (case-insensitive), e.g. ["Methods", "Experiment"]
+pub fn find_by_title_path<'a>(tree: &'a DocumentTree, titles: &[&str]) -> Option<&'a Node> {
+    let (first, rest) = titles.split_first()?;
+    let mut current = tree.top_level().iter().find(|n| same_title(&n.title, first))?;
+    for title in rest {
+        current = current.children.iter().find(|n| same_title(&n.title, title))?;
+    }
+    Some(current)
+}
+
+fn same_title(a: &str, b: &str) -> bool {
+    a.to_lowercase() == b.to_lowercase()
+}
+
+// The section whose own lines (heading through body) include the given 1-based line
+pub fn node_at_line(tree: &DocumentTree, line: usize) -> Option<&Node> {
+    tree.all_nodes()
+        .into_iter()
+        .find(|n| n.start_line <= line && line <= n.end_line)
+}
+
+// The section whose own lines contain the byte offset into the parsed source, i.e. the nearest
+// heading at or above it. None inside the preamble, past the end, or for nodes built by hand.
+pub fn node_at_offset(tree: &DocumentTree, byte_offset: usize) -> Option<&Node> {
+    tree.iter()
+        .find(|n| n.byte_start <= byte_offset && byte_offset < n.byte_end)
+}
+
+// Compares node ids between two versions; a node present in both is modified when its content hash differs
+pub fn diff(old: &DocumentTree, new: &DocumentTree) -> TreeDiff {
+    let old_hashes = old.node_hashes();
+    let new_hashes = new.node_hashes();
+    let mut result = TreeDiff::default();
+    for node in new.all_nodes() {
+        match old_hashes.get(&node.node_id) {
+            None => result.added.push(node.node_id.clone()),
+            Some(hash) if *hash != new_hashes[&node.node_id] => result.modified.push(node.node_id.clone()),
+            Some(_) => {}
+        }
+    }
+    for node in old.all_nodes() {
+        if !new_hashes.contains_key(&node.node_id) {
+            result.removed.push(node.node_id.clone());
+        }
+    }
+    result
+}
+
+// Nested <section> elements under a <document> element, e.g.
+// <section id="1.1" title="Background" depth="2"><body>...</body></section>
+pub fn to_xml(tree: &DocumentTree) -> String {
+    let mut out = format!(
+        "<document id=\"{}\" title=\"{}\">\n",
+        escape_xml(&tree.doc_id),
+        escape_xml(&tree.title)
+    );
+    for node in tree.top_level() {
+        section_xml(node, 1, &mut out);
+    }
+    out.push_str("</document>\n");
+    out
+}
+
+fn section_xml(node: &Node, level: usize, out: &mut String) {
+    let indent = "  ".repeat(level);
+    out.push_str(&format!(
+        "{}<section id=\"{}\" title=\"{}\" depth=\"{}\">\n",
+        indent,
+        escape_xml(&node.node_id),
+        escape_xml(&node.title),
+        node.depth
+    ));
+    out.push_str(&format!("{}  <body>{}</body>\n", indent, escape_xml(&node.text)));
+    if !node.children.is_empty() {
+        out.push_str(&format!("{}  <children>\n", indent));
+        for child in &node.children {
+            section_xml(child, level + 2, out);
+        }
+        out.push_str(&format!("{}  </children>\n", indent));
+    }
+    out.push_str(&format!("{}</section>\n", indent));
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn result_for(node: &Node, text: String, breadcrumb: Vec<String>) -> TraversalResult {
+    TraversalResult {
+        node_id: node.node_id.clone(),
+        title: node.title.clone(),
+        text,
+        summary: node.summary.clone(),
+        depth: node.depth,
+        breadcrumb,
+        slug: node.slug(),
+        start_line: node.start_line,
+        end_line: node.end_line,
+        page_start: node.page_start,
+        page_end: node.page_end,
+        metadata: node.metadata.clone(),
+    }
+}
+
+fn subtree_end_line(node: &Node) -> usize {
+    node.children.last().map(subtree_end_line).unwrap_or(node.end_line)
+}
+
 fn collect_subtree_text(node: &Node) -> String {
-    let mut parts = vec![node.text.clone()];
+    collect_subtree_text_mapped(node).0
+}
+
+// Subtree text plus, for every node, the (start, end) byte range of its heading and own text.
+// Descendant headings are leveled relative to `node`, which stands in for a `#` title, so its
+// children get `##` whatever depth the subtree sits at in the document.
+fn collect_subtree_text_mapped(node: &Node) -> (String, Vec<TextSpan>) {
+    let mut text = String::new();
+    let mut spans = Vec::new();
+    append_mapped(node, false, node.depth, &mut text, &mut spans);
+    (text, spans)
+}
+
+fn append_mapped(node: &Node, with_heading: bool, root_depth: usize, text: &mut String, spans: &mut Vec<TextSpan>) {
+    let level = (node.depth.saturating_sub(root_depth) + 1).max(2);
+    let heading = format!("{} {}", "#".repeat(level), node.title);
+    let pieces = [if with_heading { heading.as_str() } else { "" }, node.text.as_str()];
+    let mut start = None;
+    for piece in pieces.into_iter().filter(|p| !p.is_empty()) {
+        if !text.is_empty() {
+            text.push_str("\n\n");
+        }
+        start.get_or_insert(text.len());
+        text.push_str(piece);
+    }
+    if let Some(start) = start {
+        spans.push((start, text.len(), node.node_id.clone()));
+    }
     for child in &node.children {
-        let heading = "#".repeat(child.depth);
-        parts.push(format!("{} {}", heading, child.title));
-        parts.push(collect_subtree_text(child));
+        append_mapped(child, true, root_depth, text, spans);
     }
-    parts.into_iter().filter(|s| !s.is_empty()).collect::<Vec<_>>().join("\n\n")
 }
 
+// Titles along the actual parent links, never derived from the id, so stray dots such as `.1.`
+// or custom id schemes give an empty breadcrumb for unknown ids rather than a partial one
 fn build_breadcrumb(tree: &DocumentTree, node_id: &str) -> Vec<String> {
-    let parts: Vec<&str> = node_id.split('.').collect();
-    let mut breadcrumb = Vec::new();
-    for i in 1..=parts.len() {
-        let prefix = parts[..i].join(".");
-        if let Some(node) = tree.find_node(&prefix) {
-            breadcrumb.push(node.title.clone());
-        }
-    }
-    breadcrumb
+    tree.node_path(node_id)
+        .unwrap_or_default()
+        .iter()
+        .map(|n| n.title.clone())
+        .collect()
 }
 
-fn outline_node(node: &Node, lines: &mut Vec<String>) {
+fn outline_node(node: &Node, max_depth: Option<usize>, lines: &mut Vec<String>) {
+    let hidden = |n: &Node| max_depth.is_some_and(|max| n.depth > max);
+    if hidden(node) {
+        return;
+    }
     if node.node_id != "0" {
         let indent = "  ".repeat(node.depth.saturating_sub(1));
-        lines.push(format!("{}[{}] {}", indent, node.node_id, node.title));
+        let marker = if node.children.iter().any(hidden) { " …" } else { "" };
+        lines.push(format!("{}[{}] {}{}", indent, node.node_id, node.title, marker));
     }
     for child in &node.children {
-        outline_node(child, lines);
+        outline_node(child, max_depth, lines);
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::parser::parse_markdown;
+    use crate::parser::{parse_markdown, parse_markdown_with_options, ParseOptions};
 
     const SAMPLE: &str = r#"
 # Introduction
@@ -126,6 +542,30 @@ Experiment details.
         assert_eq!(result.text, "Background details.");
     }
 
+    #[test]
+    fn test_get_node_preview_truncates_on_char_boundary() {
+        let md = "# Notes\nCafé déjà vu, encore une fois.\n## Short\nTiny.";
+        let tree = parse_markdown("doc1", md);
+        let preview = get_node_preview(&tree, "1", 10).unwrap();
+        assert_eq!(preview.text, "Café déjà …");
+        assert_eq!(preview.text.chars().count(), 11);
+        assert_eq!(get_node_preview(&tree, "1.1", 5).unwrap().text, "Tiny.");
+        assert!(get_node_preview(&tree, "9", 10).is_none());
+    }
+
+    #[test]
+    fn test_updates_show_through_get_node() {
+        let mut tree = parse_markdown("doc1", SAMPLE);
+        assert!(tree.update_text("1.1", "Rewritten background.".to_string()));
+        assert!(tree.update_title("1.1", "Context"));
+        assert!(!tree.update_text("9", String::new()));
+        assert!(!tree.update_title("9", "Nothing"));
+        let result = get_node(&tree, "1.1").unwrap();
+        assert_eq!(result.node_id, "1.1");
+        assert_eq!(result.text, "Rewritten background.");
+        assert_eq!(result.breadcrumb, vec!["Introduction", "Context"]);
+    }
+
     #[test]
     fn test_get_node_missing_returns_none() {
         let tree = parse_markdown("doc1", SAMPLE);
@@ -139,6 +579,66 @@ Experiment details.
         assert_eq!(result.breadcrumb, vec!["Methods", "Experiment"]);
     }
 
+    #[test]
+    fn test_all_results_follow_outline_order() {
+        let tree = parse_markdown("doc1", SAMPLE);
+        let results = all_results(&tree);
+        assert_eq!(results.len(), tree.all_node_ids().len());
+        let outline_ids: Vec<String> = get_tree_outline(&tree)
+            .lines()
+            .map(|l| l.trim_start()[1..].split(']').next().unwrap().to_string())
+            .collect();
+        let ids: Vec<String> = results.iter().map(|r| r.node_id.clone()).collect();
+        assert_eq!(ids, outline_ids);
+        for result in &results {
+            assert_eq!(result.breadcrumb, get_node(&tree, &result.node_id).unwrap().breadcrumb);
+        }
+    }
+
+    // The order PageIndex.__iter__ yields nodes in
+    #[test]
+    fn test_all_results_titles_match_outline() {
+        let tree = parse_markdown("doc1", SAMPLE);
+        let titles: Vec<String> = all_results(&tree).into_iter().map(|r| r.title).collect();
+        let outline_titles: Vec<String> = get_tree_outline(&tree)
+            .lines()
+            .map(|l| l.split_once("] ").unwrap().1.to_string())
+            .collect();
+        assert_eq!(titles, outline_titles);
+    }
+
+    #[test]
+    fn test_best_content_prefers_summary() {
+        let mut tree = parse_markdown("doc1", SAMPLE);
+        tree.root.children[0].summary = Some("Intro summary.".to_string());
+        assert_eq!(get_node(&tree, "1").unwrap().best_content(), "Intro summary.");
+        assert_eq!(get_node(&tree, "2").unwrap().best_content(), "Method details.");
+    }
+
+    #[test]
+    fn test_get_node_includes_slug() {
+        let tree = parse_markdown("doc1", "# Goals & Objectives!\nText.");
+        assert_eq!(get_node(&tree, "1").unwrap().slug, "goals-objectives");
+    }
+
+    #[test]
+    fn test_get_path_pairs_ids_with_titles() {
+        let tree = parse_markdown("doc1", SAMPLE);
+        let expected = vec![
+            ("2".to_string(), "Methods".to_string()),
+            ("2.1".to_string(), "Experiment".to_string()),
+        ];
+        assert_eq!(get_path(&tree, "2.1"), expected);
+        assert!(get_path(&tree, "9").is_empty());
+    }
+
+    #[test]
+    fn test_breadcrumb_string_joins_with_separator() {
+        let tree = parse_markdown("doc1", SAMPLE);
+        let result = get_node(&tree, "2.1").unwrap();
+        assert_eq!(result.breadcrumb_string(" / "), "Methods / Experiment");
+    }
+
     #[test]
     fn test_breadcrumb_for_top_level_node() {
         let tree = parse_markdown("doc1", SAMPLE);
@@ -146,6 +646,58 @@ Experiment details.
         assert_eq!(result.breadcrumb, vec!["Introduction"]);
     }
 
+    #[test]
+    fn test_breadcrumb_follows_structure_when_ids_do_not() {
+        let mut chapter = Node::new("intro".to_string(), "Chapter".to_string(), 1, String::new());
+        let mut section = Node::new("7".to_string(), "Section".to_string(), 2, String::new());
+        section.children.push(Node::new("7.1".to_string(), "Detail".to_string(), 3, "Detail text.".to_string()));
+        chapter.children.push(section);
+        let tree = DocumentTree::new("custom".to_string(), "Chapter".to_string(), chapter);
+        let result = get_node(&tree, "7.1").unwrap();
+        assert_eq!(result.breadcrumb, vec!["Chapter", "Section", "Detail"]);
+    }
+
+    #[test]
+    fn test_breadcrumb_length_matches_depth_for_deep_node() {
+        let md = "# A\n## B\n### C.1 notes\n#### D\n##### E\n###### F\nDeep text.";
+        let tree = parse_markdown("doc1", md);
+        let result = get_node(&tree, "1.1.1.1.1.1").unwrap();
+        assert_eq!(result.breadcrumb.len(), result.depth);
+        assert_eq!(result.breadcrumb, vec!["A", "B", "C.1 notes", "D", "E", "F"]);
+        assert!(get_node(&tree, ".1.1").is_none());
+        assert!(build_breadcrumb(&tree, "1.1.").is_empty());
+    }
+
+    #[test]
+    fn test_get_node_zero_with_keep_root() {
+        let md = format!("Preamble text.\n{}", SAMPLE);
+        let options = ParseOptions::new().keep_root(true);
+        let tree = parse_markdown_with_options("doc1", &md, &options);
+        let root = get_node(&tree, "0").unwrap();
+        assert_eq!(root.title, "Introduction");
+        assert_eq!(root.text, "Preamble text.");
+        assert_eq!(root.breadcrumb, vec!["Introduction"]);
+        assert_eq!(get_node(&tree, "2.1").unwrap().breadcrumb, vec!["Methods", "Experiment"]);
+    }
+
+    #[test]
+    fn test_get_node_reports_page_span() {
+        let mut tree = parse_markdown("doc1", SAMPLE);
+        tree.set_page_span("2", 12, 13);
+        let result = get_node(&tree, "2").unwrap();
+        assert_eq!((result.page_start, result.page_end), (Some(12), Some(13)));
+        assert_eq!(get_node(&tree, "1").unwrap().page_start, None);
+    }
+
+    #[test]
+    fn test_get_node_carries_metadata() {
+        let mut tree = parse_markdown("doc1", SAMPLE);
+        tree.set_metadata("2.1", "author", "Ada");
+        let result = get_node(&tree, "2.1").unwrap();
+        assert_eq!(result.metadata.get("author").map(String::as_str), Some("Ada"));
+        assert!(get_node(&tree, "2").unwrap().metadata.is_empty());
+    }
+
     #[test]
     fn test_get_node_with_children_includes_child_text() {
         let tree = parse_markdown("doc1", SAMPLE);
@@ -154,6 +706,113 @@ Experiment details.
         assert!(result.text.contains("Goal details."));
     }
 
+    fn word_tokens(text: &str) -> usize {
+        text.split_whitespace().count()
+    }
+
+    #[test]
+    fn test_context_pack_includes_everything_within_budget() {
+        let tree = parse_markdown("doc1", SAMPLE);
+        let pack = context_pack(&tree, "2.1", 1000, &word_tokens);
+        assert!(pack.starts_with("Document outline:\n[1] Introduction"));
+        assert!(pack.contains("Path: Methods > Experiment"));
+        assert!(pack.contains("[2] Methods:\nMethod details."));
+        assert!(pack.ends_with("Section [2.1] Experiment:\nExperiment details."));
+    }
+
+    #[test]
+    fn test_context_pack_drops_outline_first_when_tight() {
+        let tree = parse_markdown("doc1", SAMPLE);
+        let pack = context_pack(&tree, "2.1", 12, &word_tokens);
+        assert!(!pack.contains("Document outline"));
+        assert!(pack.contains("Path: Methods > Experiment"));
+        assert!(pack.contains("Experiment details."));
+
+        let pack = context_pack(&tree, "2.1", 1, &word_tokens);
+        assert_eq!(pack, "Section [2.1] Experiment:\nExperiment details.");
+        assert!(context_pack(&tree, "9", 100, &word_tokens).is_empty());
+    }
+
+    #[test]
+    fn test_assemble_context_groups_under_breadcrumbs() {
+        let tree = parse_markdown("doc1", SAMPLE);
+        let ids = ["1.1", "1.2"].map(String::from);
+        let context = assemble_context(&tree, &ids, 1000);
+        assert_eq!(
+            context,
+            "# Introduction > Background\n\nBackground details.\n\n# Introduction > Goals\n\nGoal details."
+        );
+
+        let ids = ["1.2", "1", "9", "1"].map(String::from);
+        let context = assemble_context(&tree, &ids, 1000);
+        assert_eq!(context.matches("Goal details.").count(), 1);
+        assert!(context.starts_with("# Introduction\n\nIntroductory text."));
+
+        let ids = ["1.1", "2"].map(String::from);
+        let first = "# Introduction > Background\n\nBackground details.";
+        assert_eq!(assemble_context(&tree, &ids, estimate_tokens(first)), first);
+        assert!(assemble_context(&tree, &ids, 0).is_empty());
+    }
+
+    #[test]
+    fn test_assemble_context_keeps_descendant_when_ancestor_does_not_fit() {
+        let tree = parse_markdown("doc1", SAMPLE);
+        let ids = ["1.2", "1"].map(String::from);
+        let goals = "# Introduction > Goals\n\nGoal details.";
+        assert_eq!(assemble_context(&tree, &ids, estimate_tokens(goals)), goals);
+
+        let ids = ["1.2", "2", "1"].map(String::from);
+        let context = assemble_context(&tree, &ids, 1000);
+        assert!(context.starts_with("# Methods\n\n"));
+        assert!(context.contains("\n\n# Introduction\n\nIntroductory text."));
+        assert!(!context.contains("# Introduction > Goals"));
+        assert_eq!(context.matches("Goal details.").count(), 1);
+    }
+
+    #[test]
+    fn test_sibling_window_balances_before_and_after() {
+        let md = "# Doc\n## A\none\n## B\none two\n## C\none\n## D\none two three\n## E\none\n## F\none";
+        let tree = parse_markdown("doc1", md);
+        let ids = |window: Vec<TraversalResult>| window.into_iter().map(|r| r.node_id).collect::<Vec<_>>();
+        assert_eq!(ids(sibling_window(&tree, "1.3", 5, &word_tokens)), vec!["1.2", "1.3"]);
+        assert_eq!(ids(sibling_window(&tree, "1.3", 7, &word_tokens)), vec!["1.1", "1.2", "1.3", "1.4"]);
+        assert_eq!(ids(sibling_window(&tree, "1.6", 5, &word_tokens)), vec!["1.4", "1.5", "1.6"]);
+        assert_eq!(ids(sibling_window(&tree, "1.3", 0, &word_tokens)), vec!["1.3"]);
+        let window = sibling_window(&tree, "1.3", 100, &word_tokens);
+        assert_eq!(window.len(), 6);
+        assert_eq!(window[0].breadcrumb, vec!["Doc", "A"]);
+    }
+
+    #[test]
+    fn test_subtree_text_headings_are_relative_to_requested_node() {
+        let md = SAMPLE.replace("Background details.", "Background details.\n\n### Setup\nSetup notes.\n\n#### Tools\nTool list.");
+        let tree = parse_markdown("doc1", &md);
+        let text = get_node_with_children(&tree, "1.1").unwrap().text;
+        assert_eq!(text, "Background details.\n\n## Setup\n\nSetup notes.\n\n### Tools\n\nTool list.");
+        let text = get_node_with_children(&tree, "1").unwrap().text;
+        assert!(text.contains("\n## Background\n") && text.contains("\n### Setup\n"));
+    }
+
+    #[test]
+    fn test_mapped_text_matches_unmapped() {
+        let tree = parse_markdown("doc1", SAMPLE);
+        let (text, spans) = get_node_with_children_mapped(&tree, "1").unwrap();
+        assert_eq!(text, get_node_with_children(&tree, "1").unwrap().text);
+        let ids: Vec<&str> = spans.iter().map(|(_, _, id)| id.as_str()).collect();
+        assert_eq!(ids, vec!["1", "1.1", "1.2"]);
+    }
+
+    #[test]
+    fn test_mapped_offset_resolves_to_section() {
+        let tree = parse_markdown("doc1", SAMPLE);
+        let (text, spans) = get_node_with_children_mapped(&tree, "1").unwrap();
+        let offset = text.find("Background details.").unwrap() + 3;
+        let owner = spans.iter().find(|(start, end, _)| *start <= offset && offset < *end).unwrap();
+        assert_eq!(owner.2, "1.1");
+        assert_eq!(&text[owner.0..owner.1], "## Background\n\nBackground details.");
+        assert!(get_node_with_children_mapped(&tree, "9").is_none());
+    }
+
     #[test]
     fn test_get_children_returns_correct_pairs() {
         let tree = parse_markdown("doc1", SAMPLE);
@@ -163,6 +822,18 @@ Experiment details.
         assert_eq!(children[1], ("1.2".to_string(), "Goals".to_string()));
     }
 
+    #[test]
+    fn test_get_children_detailed_carries_text() {
+        let tree = parse_markdown("doc1", SAMPLE);
+        let children = get_children_detailed(&tree, "1");
+        assert_eq!(children.len(), 2);
+        assert_eq!(children[0].title, "Background");
+        assert_eq!(children[0].text, "Background details.");
+        assert_eq!(children[1].text, "Goal details.");
+        assert_eq!(children[1].breadcrumb, vec!["Introduction", "Goals"]);
+        assert!(get_children_detailed(&tree, "9").is_empty());
+    }
+
     #[test]
     fn test_get_children_leaf_node_returns_empty() {
         let tree = parse_markdown("doc1", SAMPLE);
@@ -170,6 +841,131 @@ Experiment details.
         assert!(children.is_empty());
     }
 
+    #[test]
+    fn test_find_by_title_path_resolves_nested_node() {
+        let tree = parse_markdown("doc1", SAMPLE);
+        let node = find_by_title_path(&tree, &["Methods", "Experiment"]).unwrap();
+        assert_eq!(node.node_id, "2.1");
+    }
+
+    #[test]
+    fn test_find_by_title_path_is_case_insensitive() {
+        let tree = parse_markdown("doc1", SAMPLE);
+        let node = find_by_title_path(&tree, &["introduction", "GOALS"]).unwrap();
+        assert_eq!(node.node_id, "1.2");
+    }
+
+    #[test]
+    fn test_find_by_title_path_bad_path_returns_none() {
+        let tree = parse_markdown("doc1", SAMPLE);
+        assert!(find_by_title_path(&tree, &["Methods", "Background"]).is_none());
+        assert!(find_by_title_path(&tree, &[]).is_none());
+    }
+
+    #[test]
+    fn test_diff_buckets_added_removed_and_modified() {
+        let old = parse_markdown("doc1", SAMPLE);
+        let edited = SAMPLE
+            .replace("Goal details.", "Revised goal details.")
+            .replace("## Experiment\nExperiment details.\n", "")
+            + "\n# Results\nResult details.\n";
+        let new = parse_markdown("doc1", &edited);
+        let result = diff(&old, &new);
+        assert_eq!(result.added, vec!["3"]);
+        assert_eq!(result.removed, vec!["2.1"]);
+        assert_eq!(result.modified, vec!["1.2"]);
+    }
+
+    #[test]
+    fn test_diff_identical_trees_is_empty() {
+        let tree = parse_markdown("doc1", SAMPLE);
+        assert_eq!(diff(&tree, &tree), TreeDiff::default());
+    }
+
+    #[test]
+    fn test_node_at_line_resolves_enclosing_section() {
+        let tree = parse_markdown("doc1", SAMPLE);
+        // Line 6 is "Background details."
+        assert_eq!(node_at_line(&tree, 6).unwrap().node_id, "1.1");
+        assert_eq!(node_at_line(&tree, 5).unwrap().node_id, "1.1");
+        assert_eq!(node_at_line(&tree, 4).unwrap().node_id, "1");
+        assert!(node_at_line(&tree, 1).is_none());
+        assert!(node_at_line(&tree, 100).is_none());
+    }
+
+    #[test]
+    fn test_node_at_offset_resolves_enclosing_section() {
+        let tree = parse_markdown("doc1", SAMPLE);
+        let goals = SAMPLE.find("Goal details.").unwrap() + 3;
+        assert_eq!(node_at_offset(&tree, goals).unwrap().node_id, "1.2");
+        let heading = SAMPLE.find("## Goals").unwrap();
+        assert_eq!(node_at_offset(&tree, heading).unwrap().node_id, "1.2");
+        assert_eq!(node_at_offset(&tree, heading - 1).unwrap().node_id, "1.1");
+        let node = tree.find_node("1.2").unwrap();
+        assert_eq!(&SAMPLE[node.byte_start..node.byte_end], "## Goals\nGoal details.\n\n");
+        assert!(node_at_offset(&tree, 0).is_none());
+        assert!(node_at_offset(&tree, SAMPLE.len()).is_none());
+    }
+
+    #[test]
+    fn test_text_after_list_resolves_to_its_item() {
+        let md = "# Checklist\nBefore you ship:\n\n- Tests pass\n- Changelog\n\nThat's all.";
+        let options = ParseOptions::new().list_items_as_nodes(true);
+        let tree = parse_markdown_with_options("doc1", md, &options);
+        let trailing = md.find("That's all.").unwrap();
+        assert_eq!(node_at_line(&tree, 7).unwrap().node_id, "1.2");
+        assert_eq!(node_at_offset(&tree, trailing).unwrap().node_id, "1.2");
+        assert!(node_at_offset(&tree, trailing).unwrap().text.contains("That's all."));
+        assert_eq!(node_at_line(&tree, 2).unwrap().node_id, "1");
+        // Every node's byte range holds its own text
+        for node in tree.iter() {
+            assert!(md[node.byte_start..node.byte_end].contains(node.text.as_str()), "node {}", node.node_id);
+        }
+    }
+
+    #[test]
+    fn test_to_xml_nests_children_and_escapes() {
+        let md = SAMPLE.replace("Background details.", "Latency < 5ms & \"fast\".");
+        let tree = parse_markdown("doc1", &md);
+        let xml = to_xml(&tree);
+        let intro = xml.find("<section id=\"1\" title=\"Introduction\" depth=\"1\">").unwrap();
+        let background = xml.find("<section id=\"1.1\" title=\"Background\" depth=\"2\">").unwrap();
+        let methods = xml.find("<section id=\"2\"").unwrap();
+        assert!(intro < background && background < methods);
+        let intro_close = xml[intro..].find("\n  </section>").unwrap() + intro;
+        assert!(background < intro_close);
+        assert!(xml.contains("<body>Latency &lt; 5ms &amp; &quot;fast&quot;.</body>"));
+        assert!(xml.starts_with("<document id=\"doc1\" title=\"Introduction\">"));
+    }
+
+    #[test]
+    fn test_results_carry_line_spans() {
+        let tree = parse_markdown("doc1", SAMPLE);
+        let intro = get_node(&tree, "1").unwrap();
+        assert_eq!((intro.start_line, intro.end_line), (2, 4));
+        let section = get_node_with_children(&tree, "1").unwrap();
+        assert_eq!((section.start_line, section.end_line), (2, 10));
+    }
+
+    #[test]
+    fn test_search_iter_yields_first_match_lazily() {
+        let tree = parse_markdown("doc1", SAMPLE);
+        let mut results = search_iter(&tree, "DETAILS");
+        let first = results.next().unwrap();
+        assert_eq!(first.node_id, "1.1");
+        assert_eq!(first.breadcrumb, vec!["Introduction", "Background"]);
+        let rest: Vec<String> = results.map(|r| r.node_id).collect();
+        assert_eq!(rest, vec!["1.2", "2", "2.1"]);
+    }
+
+    #[test]
+    fn test_search_iter_matches_titles() {
+        let tree = parse_markdown("doc1", SAMPLE);
+        let ids: Vec<String> = search_iter(&tree, "goals").map(|r| r.node_id).collect();
+        assert_eq!(ids, vec!["1.2"]);
+        assert!(search_iter(&tree, "nowhere").next().is_none());
+    }
+
     #[test]
     fn test_outline_contains_all_nodes() {
         let tree = parse_markdown("doc1", SAMPLE);
@@ -180,6 +976,30 @@ Experiment details.
         assert!(outline.contains("[2.1] Experiment"));
     }
 
+    #[test]
+    fn test_collapsed_outline_marks_hidden_children() {
+        let md = format!("{}\n# Appendix\nNotes.", SAMPLE);
+        let tree = parse_markdown("doc1", &md);
+        let outline = get_tree_outline_collapsed(&tree, 1);
+        assert_eq!(outline, "[1] Introduction …\n[2] Methods …\n[3] Appendix");
+        assert_eq!(get_tree_outline_collapsed(&tree, 2), get_tree_outline(&tree));
+    }
+
+    #[test]
+    fn test_fingerprint_outline_is_stable_and_content_sensitive() {
+        let first = get_tree_fingerprint_outline(&parse_markdown("doc1", SAMPLE));
+        let second = get_tree_fingerprint_outline(&parse_markdown("doc1", SAMPLE));
+        assert_eq!(first, second);
+        assert_eq!(first.lines().count(), 5);
+        assert!(first.lines().nth(1).unwrap().starts_with("[1.1] (2) <"));
+        assert!(first.lines().nth(1).unwrap().ends_with("> Background"));
+
+        let edited = get_tree_fingerprint_outline(&parse_markdown("doc1", &SAMPLE.replace("Goal details.", "Goals.")));
+        assert_ne!(first, edited);
+        assert_eq!(first.lines().nth(1), edited.lines().nth(1));
+        assert_ne!(first.lines().nth(2), edited.lines().nth(2));
+    }
+
     #[test]
     fn test_outline_indentation() {
         let tree = parse_markdown("doc1", SAMPLE);
@@ -1,5 +1,9 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
 use serde::{Deserialize, Serialize};
 
+use crate::error::PageIndexError;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Node {
     pub node_id: String,
@@ -8,6 +12,32 @@ pub struct Node {
     pub text: String,
     pub summary: Option<String>,
     pub children: Vec<Node>,
+    // 1-based, inclusive source lines from the heading to just before the next heading; 0 when the
+    // node did not come from parsed source (e.g. the synthetic root)
+    #[serde(default)]
+    pub start_line: usize,
+    #[serde(default)]
+    pub end_line: usize,
+    // Byte range of the same lines in the source, end exclusive and including the final newline
+    #[serde(default)]
+    pub byte_start: usize,
+    #[serde(default)]
+    pub byte_end: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub embedding: Option<Vec<f32>>,
+    // Heading text as written, set only when parsing rewrote `title` (e.g. strip_leading_numbers)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_title: Option<String>,
+    // Inclusive page range in the original document (e.g. the PDF the markdown came from), set
+    // through DocumentTree::set_page_span; markdown itself has no pages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page_start: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page_end: Option<usize>,
+    // Free-form per-section key/value data such as a source URL or tags, like the document-level
+    // DocumentTree.attributes
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
 }
 
 impl Node {
@@ -19,6 +49,15 @@ impl Node {
             text,
             summary: None,
             children: Vec::new(),
+            start_line: 0,
+            end_line: 0,
+            byte_start: 0,
+            byte_end: 0,
+            embedding: None,
+            raw_title: None,
+            page_start: None,
+            page_end: None,
+            metadata: BTreeMap::new(),
         }
     }
 
@@ -34,6 +73,72 @@ impl Node {
         None
     }
 
+    pub fn set_embedding(&mut self, embedding: Vec<f32>) {
+        self.embedding = Some(embedding);
+    }
+
+    // Chain of nodes from self down to the node with this id, both ends included
+    pub fn path_to(&self, node_id: &str) -> Option<Vec<&Node>> {
+        if self.node_id == node_id {
+            return Some(vec![self]);
+        }
+        self.children.iter().find_map(|c| {
+            let mut path = c.path_to(node_id)?;
+            path.insert(0, self);
+            Some(path)
+        })
+    }
+
+    pub fn find_mut(&mut self, node_id: &str) -> Option<&mut Node> {
+        if self.node_id == node_id {
+            return Some(self);
+        }
+        self.children.iter_mut().find_map(|c| c.find_mut(node_id))
+    }
+
+    // Removes the descendant with this id (and its subtree) from wherever it sits below self
+    fn detach(&mut self, node_id: &str) -> Option<Node> {
+        if let Some(pos) = self.children.iter().position(|c| c.node_id == node_id) {
+            return Some(self.children.remove(pos));
+        }
+        self.children.iter_mut().find_map(|c| c.detach(node_id))
+    }
+
+    // Moves this subtree to a new depth, keeping descendants' relative levels
+    fn rebase_depth(&mut self, depth: usize) {
+        let old = self.depth;
+        self.depth = depth;
+        for child in &mut self.children {
+            let relative = child.depth.saturating_sub(old).max(1);
+            child.rebase_depth(depth + relative);
+        }
+    }
+
+    fn drop_children_where(&mut self, matches: &dyn Fn(&Node) -> bool) {
+        self.children.retain(|c| !matches(c));
+        for child in &mut self.children {
+            child.drop_children_where(matches);
+        }
+    }
+
+    // Bottom-up, so a parent whose children were all empty is itself removed when it has no text
+    fn prune_empty_children(&mut self) {
+        for child in &mut self.children {
+            child.prune_empty_children();
+        }
+        self.children.retain(|c| !c.text.is_empty() || !c.children.is_empty());
+    }
+
+    // Number of nodes in this subtree, self included, without allocating
+    pub fn count(&self) -> usize {
+        1 + self.children.iter().map(|c| c.count()).sum::<usize>()
+    }
+
+    // Levels in this subtree, 1 for a leaf
+    pub fn height(&self) -> usize {
+        1 + self.children.iter().map(|c| c.height()).max().unwrap_or(0)
+    }
+
     pub fn all_ids(&self) -> Vec<String> {
         let mut ids = vec![self.node_id.clone()];
         for child in &self.children {
@@ -42,6 +147,179 @@ impl Node {
         ids
     }
 
+    // Contents of `code` spans in this node's own text, following the CommonMark rules: a span closes
+    // at the next backtick run of the same length, and a backslash-escaped backtick opens nothing
+    pub fn inline_code(&self) -> Vec<String> {
+        let text = self.text.as_str();
+        let bytes = text.as_bytes();
+        let mut spans = Vec::new();
+        let mut i = 0;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'\\' => i += 2,
+                b'`' => {
+                    let open = backtick_run(bytes, i);
+                    let start = i + open;
+                    let mut k = start;
+                    i = start;
+                    while k < bytes.len() {
+                        if bytes[k] != b'`' {
+                            k += 1;
+                            continue;
+                        }
+                        let close = backtick_run(bytes, k);
+                        if close == open {
+                            spans.push(normalize_code_span(&text[start..k]));
+                            i = k + close;
+                            break;
+                        }
+                        k += close;
+                    }
+                }
+                _ => i += 1,
+            }
+        }
+        spans
+    }
+
+    // (text, url) for each inline `[text](url)` link in this node's own text, in order. Images
+    // (`![alt](src)`) are skipped, as are reference-style `[text][ref]` links, whose targets live
+    // elsewhere in the document. An optional link title (`[a](url "title")`) is dropped from the url.
+    pub fn links(&self) -> Vec<(String, String)> {
+        inline_targets(&self.text)
+            .into_iter()
+            .filter(|(image, _, _)| !image)
+            .map(|(_, text, url)| (text, url))
+            .collect()
+    }
+
+    // (alt, src) for each inline `![alt](src)` image in this node's own text, in order
+    pub fn images(&self) -> Vec<(String, String)> {
+        inline_targets(&self.text)
+            .into_iter()
+            .filter(|(image, _, _)| *image)
+            .map(|(_, alt, src)| (alt, src))
+            .collect()
+    }
+
+    // FNV-1a over title and own text (children excluded), so hashes are stable across runs and platforms
+    pub fn content_hash(&self) -> u64 {
+        let mut hash = FNV_OFFSET_BASIS;
+        for byte in self.title.bytes().chain([0u8]).chain(self.text.bytes()) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+        hash
+    }
+
+    // URL-friendly form of the title: lowercased, whitespace runs turned into single hyphens and
+    // anything that isn't a letter, digit or hyphen dropped, so "Goals & Objectives!" becomes
+    // `goals-objectives`. Letters outside ASCII are kept and lowercased.
+    pub fn slug(&self) -> String {
+        let mut slug = String::new();
+        for c in self.title.chars().flat_map(|c| c.to_lowercase()) {
+            if c.is_alphanumeric() {
+                slug.push(c);
+            } else if (c.is_whitespace() || c == '-') && !slug.is_empty() && !slug.ends_with('-') {
+                slug.push('-');
+            }
+        }
+        while slug.ends_with('-') {
+            slug.pop();
+        }
+        slug
+    }
+
+    // Whitespace-separated words in this node's own text
+    pub fn word_count(&self) -> usize {
+        self.text.split_whitespace().count()
+    }
+
+    pub fn subtree_word_count(&self) -> usize {
+        self.word_count() + self.children.iter().map(|c| c.subtree_word_count()).sum::<usize>()
+    }
+
+    // Minutes to read this node's own text at `wpm` words per minute (200 is typical)
+    pub fn reading_time_minutes(&self, wpm: usize) -> f32 {
+        self.word_count() as f32 / wpm.max(1) as f32
+    }
+
+    pub fn subtree_reading_time_minutes(&self, wpm: usize) -> f32 {
+        self.subtree_word_count() as f32 / wpm.max(1) as f32
+    }
+
+    // The `n` most frequent words of this node's own text with their counts, lowercased and split
+    // at anything that isn't a letter or digit. Stopwords, single characters and bare numbers are
+    // skipped. Ties go alphabetically, so the result is deterministic.
+    pub fn top_keywords(&self, n: usize) -> Vec<(String, usize)> {
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for word in self.text.split(|c: char| !c.is_alphanumeric()) {
+            let word = word.to_lowercase();
+            let too_plain = word.chars().count() < 2 || word.chars().all(|c| c.is_numeric());
+            if too_plain || STOPWORDS.contains(&word.as_str()) {
+                continue;
+            }
+            *counts.entry(word).or_default() += 1;
+        }
+        let mut ranked: Vec<(String, usize)> = counts.into_iter().collect();
+        ranked.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        ranked.truncate(n);
+        ranked
+    }
+
+    // Fraction of distinct lowercased words the two nodes' own texts share, in 0.0..=1.0.
+    // Two empty texts score 0.0 so blank sections never look alike.
+    pub fn jaccard_similarity(&self, other: &Node) -> f32 {
+        jaccard(&self.word_set(), &other.word_set())
+    }
+
+    fn word_set(&self) -> HashSet<String> {
+        self.text
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|w| !w.is_empty())
+            .map(str::to_lowercase)
+            .collect()
+    }
+
+    // A copy keeping listed nodes with their whole subtree, plus the ancestors leading to them
+    fn retained(&self, keep_ids: &[&str]) -> Option<Node> {
+        if keep_ids.contains(&self.node_id.as_str()) {
+            return Some(self.clone());
+        }
+        let children: Vec<Node> = self.children.iter().filter_map(|c| c.retained(keep_ids)).collect();
+        if children.is_empty() {
+            return None;
+        }
+        let mut node = self.clone();
+        node.children = children;
+        Some(node)
+    }
+
+    fn rollup_summaries(&mut self, joiner: &str) {
+        for child in &mut self.children {
+            child.rollup_summaries(joiner);
+        }
+        if self.summary.is_some() || self.children.is_empty() {
+            return;
+        }
+        let parts: Vec<&str> = self.children.iter().filter_map(|c| c.summary.as_deref()).collect();
+        if !parts.is_empty() {
+            self.summary = Some(parts.join(joiner));
+        }
+    }
+
+    // Clears text, summary and embedding throughout this subtree, leaving only structure
+    fn strip_content(&mut self) {
+        self.text.clear();
+        self.summary = None;
+        self.embedding = None;
+        self.raw_title = None;
+        self.metadata.clear();
+        for child in &mut self.children {
+            child.strip_content();
+        }
+    }
+
     pub fn flatten(&self) -> Vec<&Node> {
         let mut nodes = vec![self as &Node];
         for child in &self.children {
@@ -51,12 +329,240 @@ impl Node {
     }
 }
 
+// Recomputes descendant ids from position, mirroring the numbering build_tree produces. Every
+// structural edit goes through this, so ids are a pure function of the current child order.
+pub(crate) fn renumber_children(parent: &mut Node, separator: &str) {
+    for (i, child) in parent.children.iter_mut().enumerate() {
+        child.node_id = positional_id(&parent.node_id, i, separator);
+        renumber_children(child, separator);
+    }
+}
+
+// Id of the child at `index` under a parent with `parent_id`
+fn positional_id(parent_id: &str, index: usize, separator: &str) -> String {
+    if parent_id == "0" {
+        (index + 1).to_string()
+    } else {
+        format!("{}{}{}", parent_id, separator, index + 1)
+    }
+}
+
+fn ids_match_positions(parent: &Node, separator: &str) -> bool {
+    parent.children.iter().enumerate().all(|(i, child)| {
+        child.node_id == positional_id(&parent.node_id, i, separator) && ids_match_positions(child, separator)
+    })
+}
+
+fn default_id_separator() -> String {
+    ".".to_string()
+}
+
+// For a line opening a numbered paragraph, the count of number parts and the text after the
+// number. A lone number needs a trailing dot (`3.`) so lines like `2024 was busy` don't match.
+fn paragraph_number(line: &str) -> Option<(usize, &str)> {
+    let (marker, rest) = line.trim_start().split_once(char::is_whitespace)?;
+    let trailing_dot = marker.ends_with('.');
+    let number = marker.strip_suffix('.').unwrap_or(marker);
+    let parts = number.split('.').count();
+    if !number.split('.').all(|p| !p.is_empty() && p.bytes().all(|b| b.is_ascii_digit())) {
+        return None;
+    }
+    if parts == 1 && !trailing_dot {
+        return None;
+    }
+    let rest = rest.trim();
+    (!rest.is_empty()).then_some((parts, rest))
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> Option<f32> {
+    if a.len() != b.len() || a.is_empty() {
+        return None;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return None;
+    }
+    Some(dot / (norm_a * norm_b))
+}
+
+fn jaccard(a: &HashSet<String>, b: &HashSet<String>) -> f32 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    a.intersection(b).count() as f32 / union as f32
+}
+
+fn to_json_string<T: Serialize + ?Sized>(value: &T) -> Result<String, PageIndexError> {
+    Ok(serde_json::to_string_pretty(value)?)
+}
+
+// Quotes a CSV field that needs it, doubling any quotes inside
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// Common English function words, too frequent to say anything about a section
+const STOPWORDS: &[&str] = &[
+    "a", "about", "after", "all", "also", "an", "and", "any", "are", "as", "at", "be", "been", "but", "by",
+    "can", "could", "did", "do", "does", "each", "for", "from", "had", "has", "have", "he", "her", "his",
+    "how", "if", "in", "into", "is", "it", "its", "may", "more", "most", "must", "no", "not", "of", "on",
+    "one", "or", "other", "our", "out", "over", "she", "should", "so", "some", "such", "than", "that",
+    "the", "their", "them", "then", "there", "these", "they", "this", "those", "through", "to", "up",
+    "us", "was", "we", "were", "what", "when", "where", "which", "while", "who", "will", "with", "would",
+    "you", "your",
+];
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+fn backtick_run(bytes: &[u8], start: usize) -> usize {
+    bytes[start..].iter().take_while(|b| **b == b'`').count()
+}
+
+// Every inline link or image as (is_image, text, destination), in order of appearance
+fn inline_targets(text: &str) -> Vec<(bool, String, String)> {
+    let bytes = text.as_bytes();
+    let mut targets = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' {
+            i += 2;
+            continue;
+        }
+        let image = bytes[i] == b'!' && bytes.get(i + 1) == Some(&b'[');
+        if bytes[i] != b'[' && !image {
+            i += 1;
+            continue;
+        }
+        let open = if image { i + 1 } else { i };
+        let Some(close) = closing_bracket(bytes, open, b'[', b']') else {
+            i = open + 1;
+            continue;
+        };
+        let url_end = match bytes.get(close + 1) {
+            Some(b'(') => closing_bracket(bytes, close + 1, b'(', b')'),
+            _ => None,
+        };
+        match url_end {
+            Some(end) => {
+                let label = text[open + 1..close].to_string();
+                targets.push((image, label, link_destination(&text[close + 2..end])));
+                i = end + 1;
+            }
+            None => i = close + 1,
+        }
+    }
+    targets
+}
+
+// Index of the bracket closing the one at `open`, honouring nesting and backslash escapes
+fn closing_bracket(bytes: &[u8], open: usize, left: u8, right: u8) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = open;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b if b == left => depth += 1,
+            b if b == right => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            b'\n' if left == b'(' => return None,
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+// The url part of `(url "title")`, without the title or surrounding angle brackets
+fn link_destination(raw: &str) -> String {
+    let raw = raw.trim();
+    if let Some(rest) = raw.strip_prefix('<') {
+        if let Some(end) = rest.find('>') {
+            return rest[..end].to_string();
+        }
+    }
+    raw.split_whitespace().next().unwrap_or_default().to_string()
+}
+
+fn normalize_code_span(raw: &str) -> String {
+    let content = raw.replace('\n', " ");
+    let padded = content.len() >= 2 && content.starts_with(' ') && content.ends_with(' ');
+    if padded && !content.chars().all(|c| c == ' ') {
+        content[1..content.len() - 1].to_string()
+    } else {
+        content
+    }
+}
+
+// One row of the flat export: a node without its children, pointing at its parent instead
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FlatNode {
+    pub node_id: String,
+    pub parent_id: Option<String>,
+    pub title: String,
+    pub depth: usize,
+    pub text: String,
+    pub summary: Option<String>,
+}
+
+// Shape summary for judging whether a parse produced a useful hierarchy; a huge max_fanout
+// usually means the source had few real heading levels
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructureStats {
+    // Nesting levels below the document, 1 when there are only top-level sections
+    pub max_depth: usize,
+    pub node_count: usize,
+    pub leaf_count: usize,
+    // Most children under a single parent; the document itself counts as the parent of the
+    // top-level sections
+    pub max_fanout: usize,
+    // Mean child count over parents that have any children, 0.0 for an empty document
+    pub avg_fanout: f64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DocumentTree {
     pub doc_id: String,
     pub title: String,
     pub description: Option<String>,
     pub root: Node,
+    // Free-form document metadata such as author or source URL, filled from front matter when enabled
+    #[serde(default)]
+    pub attributes: BTreeMap<String, String>,
+    // Joins the parts of generated ids (`2.1` with the default `.`); also used when renumbering
+    #[serde(default = "default_id_separator")]
+    pub id_separator: String,
+    // Text the parser found before the first heading, which no section owns. With keep_root it
+    // becomes node `0`'s text instead and this stays empty.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub preamble: String,
+    // Version of the JSON layout this tree was written with; JSON predating the field is version 1
+    #[serde(default = "legacy_schema_version")]
+    pub schema_version: u32,
+}
+
+// Bumped whenever the serialized layout changes. Version 2 added line and byte spans, page spans,
+// raw titles, node metadata, attributes, the id separator and the preamble, all of which default
+// when absent.
+pub const SCHEMA_VERSION: u32 = 2;
+
+fn legacy_schema_version() -> u32 {
+    1
 }
 
 impl DocumentTree {
@@ -66,91 +572,1776 @@ impl DocumentTree {
             title,
             description: None,
             root,
+            attributes: BTreeMap::new(),
+            id_separator: default_id_separator(),
+            preamble: String::new(),
+            schema_version: SCHEMA_VERSION,
+        }
+    }
+
+    pub fn attribute(&self, key: &str) -> Option<&str> {
+        self.attributes.get(key).map(|v| v.as_str())
+    }
+
+    // Returns the previous value, if any
+    pub fn set_attribute(&mut self, key: &str, value: &str) -> Option<String> {
+        self.attributes.insert(key.to_string(), value.to_string())
+    }
+
+    pub fn remove_attribute(&mut self, key: &str) -> Option<String> {
+        self.attributes.remove(key)
+    }
+
+    // Combines documents under one synthetic root, each tree's top-level sections following the
+    // previous tree's, renumbered so ids don't collide. Ids use the first tree's separator, and
+    // the text each tree had before its first heading (its preamble, or its root text under
+    // keep_root) is joined, in order, into the merged preamble.
+    pub fn merge(doc_id: &str, title: &str, trees: Vec<DocumentTree>) -> DocumentTree {
+        let mut root = Node::new("0".to_string(), "root".to_string(), 0, String::new());
+        let id_separator = trees.first().map_or_else(default_id_separator, |t| t.id_separator.clone());
+        let mut preambles = Vec::new();
+        for tree in trees {
+            if !tree.preamble.is_empty() {
+                preambles.push(tree.preamble);
+            }
+            if tree.root.node_id == "0" {
+                if !tree.root.text.is_empty() {
+                    preambles.push(tree.root.text);
+                }
+                root.children.extend(tree.root.children);
+            } else {
+                root.children.push(tree.root);
+            }
         }
+        let mut merged = DocumentTree::new(doc_id.to_string(), title.to_string(), root);
+        merged.id_separator = id_separator;
+        merged.preamble = preambles.join("\n\n");
+        merged.renumber();
+        merged
     }
 
     pub fn find_node(&self, node_id: &str) -> Option<&Node> {
         self.root.find(node_id)
     }
 
-    // Excludes the synthetic root node used when a document has multiple top-level headings
-    pub fn all_node_ids(&self) -> Vec<String> {
-        if self.root.node_id == "0" {
-            self.root.children.iter().flat_map(|c| c.all_ids()).collect()
-        } else {
-            self.root.all_ids()
+    // find_node for callers that want a missing id as an error
+    pub fn require_node(&self, node_id: &str) -> Result<&Node, PageIndexError> {
+        self.find_node(node_id)
+            .ok_or_else(|| PageIndexError::NotFound(node_id.to_string()))
+    }
+
+    // Whether find_node would succeed, stopping at the first match without building anything
+    pub fn contains(&self, node_id: &str) -> bool {
+        self.root.find(node_id).is_some()
+    }
+
+    // Every node with this title, in document order; titles like "Overview" often repeat
+    pub fn find_nodes_by_title(&self, title: &str, case_insensitive: bool) -> Vec<&Node> {
+        let wanted = title.to_lowercase();
+        self.iter()
+            .filter(|n| {
+                if case_insensitive {
+                    n.title.to_lowercase() == wanted
+                } else {
+                    n.title == title
+                }
+            })
+            .collect()
+    }
+
+    // Ancestors of a node followed by the node itself, found by walking the tree. The synthetic
+    // root is left out unless it is the node asked for.
+    pub fn node_path(&self, node_id: &str) -> Option<Vec<&Node>> {
+        let mut path = self.root.path_to(node_id)?;
+        if path.len() > 1 && self.root.node_id == "0" {
+            path.remove(0);
         }
+        Some(path)
     }
 
-    pub fn all_nodes(&self) -> Vec<&Node> {
+    // The deepest node whose subtree holds every listed id (a node counts as containing itself),
+    // so sections in different top-level chapters resolve to the root. None when `ids` is empty or
+    // any id is unknown.
+    pub fn enclosing_node(&self, ids: &[&str]) -> Option<&Node> {
+        let (first, rest) = ids.split_first()?;
+        let mut common = self.root.path_to(first)?;
+        for id in rest {
+            let path = self.root.path_to(id)?;
+            let shared = common
+                .iter()
+                .zip(&path)
+                .take_while(|(a, b)| std::ptr::eq(**a, **b))
+                .count();
+            common.truncate(shared);
+        }
+        common.last().copied()
+    }
+
+    // True when, for every node, the titles found by resolving each dotted id prefix match the
+    // titles on the actual path through the tree. Hand-built trees with custom ids can fail this.
+    pub fn verify_breadcrumbs(&self) -> bool {
+        self.iter().all(|node| {
+            let walked: Vec<&str> = self
+                .node_path(&node.node_id)
+                .unwrap_or_default()
+                .iter()
+                .map(|n| n.title.as_str())
+                .collect();
+            walked == self.id_prefix_titles(&node.node_id)
+        })
+    }
+
+    fn id_prefix_titles(&self, node_id: &str) -> Vec<&str> {
+        let parts: Vec<&str> = node_id.split(self.id_separator.as_str()).collect();
+        (1..=parts.len())
+            .filter_map(|i| self.find_node(&parts[..i].join(&self.id_separator)))
+            .map(|n| n.title.as_str())
+            .collect()
+    }
+
+    // The real top-level sections: the synthetic root's children, or the promoted root itself
+    pub fn top_level(&self) -> &[Node] {
         if self.root.node_id == "0" {
-            self.root.children.iter().flat_map(|c| c.flatten()).collect()
+            &self.root.children
         } else {
-            self.root.flatten()
+            std::slice::from_ref(&self.root)
         }
     }
 
-    pub fn to_json(&self) -> String {
-        serde_json::to_string_pretty(self).unwrap_or_default()
+    // Lazy depth-first walk over the real nodes in document order
+    pub fn iter(&self) -> Nodes<'_> {
+        Nodes {
+            stack: self.top_level().iter().rev().collect(),
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    // Calls `f` for every real node in document order with its ancestors, outermost first and
+    // without the synthetic root, as node_path would give them. One pass, no per-node lookups.
+    pub fn walk<F: FnMut(&Node, &[&Node])>(&self, mut f: F) {
+        fn visit<'a, F: FnMut(&Node, &[&Node])>(node: &'a Node, ancestors: &mut Vec<&'a Node>, f: &mut F) {
+            f(node, ancestors);
+            ancestors.push(node);
+            for child in &node.children {
+                visit(child, ancestors, f);
+            }
+            ancestors.pop();
+        }
+        let mut ancestors = Vec::new();
+        for node in self.top_level() {
+            visit(node, &mut ancestors, &mut f);
+        }
+    }
 
-    fn make_tree() -> DocumentTree {
-        let child1 = Node::new("1.1".to_string(), "Background".to_string(), 2, "Background text.".to_string());
-        let child2 = Node::new("1.2".to_string(), "Goals".to_string(), 2, "Goals text.".to_string());
-        let mut root = Node::new("1".to_string(), "Introduction".to_string(), 1, "Intro text.".to_string());
-        root.children.push(child1);
-        root.children.push(child2);
-        DocumentTree::new("doc1".to_string(), "Introduction".to_string(), root)
+    // Real nodes level by level: every top-level section, then all of their children, and so on,
+    // each level in document order
+    pub fn bfs_nodes(&self) -> Vec<&Node> {
+        let mut nodes: Vec<&Node> = self.top_level().iter().collect();
+        let mut next = 0;
+        while next < nodes.len() {
+            let node = nodes[next];
+            nodes.extend(node.children.iter());
+            next += 1;
+        }
+        nodes
     }
 
-    #[test]
-    fn test_find_root_node() {
-        let tree = make_tree();
-        let node = tree.find_node("1");
-        assert!(node.is_some());
-        assert_eq!(node.unwrap().title, "Introduction");
+    // Same as all_node_ids().len() without building the Vec
+    pub fn node_count(&self) -> usize {
+        self.top_level().iter().map(|n| n.count()).sum()
     }
 
-    #[test]
-    fn test_find_child_node() {
-        let tree = make_tree();
-        let node = tree.find_node("1.2");
-        assert!(node.is_some());
-        assert_eq!(node.unwrap().title, "Goals");
+    // Structural levels below the document, like structure_stats().max_depth; heading levels
+    // skipped in the source do not count
+    pub fn max_depth(&self) -> usize {
+        self.top_level().iter().map(|n| n.height()).max().unwrap_or(0)
     }
 
-    #[test]
-    fn test_find_missing_node_returns_none() {
-        let tree = make_tree();
-        assert!(tree.find_node("9.9").is_none());
+    // Excludes the synthetic root node used when a document has multiple top-level headings.
+    // Order is the tree walk (parents before children, siblings in child order).
+    pub fn all_node_ids(&self) -> Vec<String> {
+        self.top_level().iter().flat_map(|c| c.all_ids()).collect()
     }
 
-    #[test]
-    fn test_all_node_ids() {
-        let tree = make_tree();
-        let ids = tree.all_node_ids();
-        assert_eq!(ids.len(), 3);
-        assert!(ids.contains(&"1".to_string()));
-        assert!(ids.contains(&"1.1".to_string()));
-        assert!(ids.contains(&"1.2".to_string()));
+    // Ids ordered by their numeric parts rather than as strings, so `1.2` comes before `1.10` and
+    // `1` before `1.1`. For parsed or renumbered trees this equals all_node_ids; for hand-built
+    // trees it gives the same order whatever sequence the children were added in.
+    pub fn all_node_ids_sorted(&self) -> Vec<String> {
+        let mut ids = self.all_node_ids();
+        ids.sort_by(|a, b| {
+            let parts = |id: &str| -> Vec<(u64, String)> {
+                id.split(self.id_separator.as_str())
+                    .map(|p| (p.parse().unwrap_or(u64::MAX), p.to_string()))
+                    .collect()
+            };
+            parts(a).cmp(&parts(b))
+        });
+        ids
     }
 
-    #[test]
-    fn test_flatten_count() {
-        let tree = make_tree();
-        assert_eq!(tree.all_nodes().len(), 3);
+    // Ids a tree view collapsed to `max_depth` would show: nodes at that depth or shallower, in
+    // document order. Deeper nodes are skipped without descending, as their ancestors are collapsed.
+    pub fn visible_ids(&self, max_depth: usize) -> Vec<String> {
+        fn visit(node: &Node, max_depth: usize, out: &mut Vec<String>) {
+            if node.depth > max_depth {
+                return;
+            }
+            out.push(node.node_id.clone());
+            for child in &node.children {
+                visit(child, max_depth, out);
+            }
+        }
+        let mut out = Vec::new();
+        for node in self.top_level() {
+            visit(node, max_depth, &mut out);
+        }
+        out
     }
 
-    #[test]
-    fn test_to_json_contains_title() {
-        let tree = make_tree();
-        let json = tree.to_json();
-        assert!(json.contains("Introduction"));
-        assert!(json.contains("1.1"));
+    // Text that belongs to no real section, as (owner, text): the preamble and any text on the
+    // synthetic root, both reported under `0`. Empty when every line is inside some section.
+    pub fn orphan_text(&self) -> Vec<(String, String)> {
+        let mut orphans = Vec::new();
+        if !self.preamble.is_empty() {
+            orphans.push(("0".to_string(), self.preamble.clone()));
+        }
+        if self.root.node_id == "0" && !self.root.text.is_empty() {
+            orphans.push(("0".to_string(), self.root.text.clone()));
+        }
+        orphans
+    }
+
+    pub fn structure_stats(&self) -> StructureStats {
+        fn visit(node: &Node, level: usize, stats: &mut StructureStats, parents: &mut usize, children: &mut usize) {
+            stats.node_count += 1;
+            stats.max_depth = stats.max_depth.max(level);
+            if node.children.is_empty() {
+                stats.leaf_count += 1;
+            } else {
+                *parents += 1;
+                *children += node.children.len();
+                stats.max_fanout = stats.max_fanout.max(node.children.len());
+            }
+            for child in &node.children {
+                visit(child, level + 1, stats, parents, children);
+            }
+        }
+        let mut stats = StructureStats {
+            max_depth: 0,
+            node_count: 0,
+            leaf_count: 0,
+            max_fanout: 0,
+            avg_fanout: 0.0,
+        };
+        let top = self.top_level();
+        let (mut parents, mut children) = (0, 0);
+        if !top.is_empty() {
+            parents += 1;
+            children += top.len();
+            stats.max_fanout = top.len();
+        }
+        for node in top {
+            visit(node, 1, &mut stats, &mut parents, &mut children);
+        }
+        if parents > 0 {
+            stats.avg_fanout = children as f64 / parents as f64;
+        }
+        stats
+    }
+
+    // Every node title in document order, borrowed from the tree
+    pub fn titles(&self) -> Vec<&str> {
+        self.iter().map(|n| n.title.as_str()).collect()
+    }
+
+    pub fn all_nodes(&self) -> Vec<&Node> {
+        self.top_level().iter().flat_map(|c| c.flatten()).collect()
+    }
+
+    // Filtered copy containing each listed node, its subtree and its ancestor chain. Ids are not
+    // renumbered, so they still match the original tree.
+    pub fn retain(&self, keep_ids: &[&str]) -> DocumentTree {
+        let root = self.root.retained(keep_ids).unwrap_or_else(|| {
+            let mut empty = self.root.clone();
+            empty.children.clear();
+            empty
+        });
+        self.with_root(root)
+    }
+
+    // Same document metadata over a different node structure
+    fn with_root(&self, root: Node) -> DocumentTree {
+        DocumentTree {
+            doc_id: self.doc_id.clone(),
+            title: self.title.clone(),
+            description: self.description.clone(),
+            root,
+            attributes: self.attributes.clone(),
+            id_separator: self.id_separator.clone(),
+            preamble: self.preamble.clone(),
+            schema_version: self.schema_version,
+        }
+    }
+
+    // Share of the document's words that live in this node's subtree, in 0.0..=1.0
+    pub fn subtree_fraction(&self, node_id: &str) -> Option<f64> {
+        let node = self.find_node(node_id)?;
+        let total = self.root.subtree_word_count();
+        if total == 0 {
+            return Some(0.0);
+        }
+        Some(node.subtree_word_count() as f64 / total as f64)
+    }
+
+    // Moves a node and its subtree to position `index` among `new_parent_id`'s children (clamped to
+    // the end), then renumbers ids. Returns false for unknown ids, the root, or a move under itself.
+    pub fn reparent(&mut self, node_id: &str, new_parent_id: &str, index: usize) -> bool {
+        if node_id == self.root.node_id || self.find_node(new_parent_id).is_none() {
+            return false;
+        }
+        match self.find_node(node_id) {
+            Some(node) if node.find(new_parent_id).is_none() => {}
+            _ => return false,
+        }
+        let Some(mut moved) = self.root.detach(node_id) else {
+            return false;
+        };
+        let parent = self.root.find_mut(new_parent_id).expect("parent checked above");
+        moved.rebase_depth(parent.depth + 1);
+        let index = index.min(parent.children.len());
+        parent.children.insert(index, moved);
+        self.renumber();
+        true
+    }
+
+    // Recomputes every node_id from its position (`1`, `1.1`, `1.2`, `2`, ...) after structural edits.
+    // The root keeps its id; parser output that skipped heading levels gets gap-free ids.
+    pub fn renumber(&mut self) {
+        renumber_children(&mut self.root, &self.id_separator);
+    }
+
+    // True when every id is what renumber would assign, i.e. ids reflect positions. Hand-built
+    // trees and parses that skipped heading levels (`1.0.1`) can fail this.
+    pub fn verify_id_consistency(&self) -> bool {
+        ids_match_positions(&self.root, &self.id_separator)
+    }
+
+    // Copy without any section (and its subtree) whose title is in `titles`, e.g. boilerplate like
+    // "Table of Contents" or "Copyright", renumbered afterwards. A matching promoted root takes the
+    // whole document with it, leaving an empty synthetic root `0`.
+    pub fn drop_titles(&self, titles: &[&str], case_insensitive: bool) -> DocumentTree {
+        let lowered: Vec<String> = if case_insensitive {
+            titles.iter().map(|t| t.to_lowercase()).collect()
+        } else {
+            Vec::new()
+        };
+        let matches = |node: &Node| {
+            if case_insensitive {
+                let title = node.title.to_lowercase();
+                lowered.contains(&title)
+            } else {
+                titles.iter().any(|t| *t == node.title)
+            }
+        };
+        if self.root.node_id != "0" && matches(&self.root) {
+            return self.with_root(Node::new("0".to_string(), "root".to_string(), 0, String::new()));
+        }
+        let mut root = self.root.clone();
+        root.drop_children_where(&matches);
+        let mut tree = self.with_root(root);
+        tree.renumber();
+        tree
+    }
+
+    // A standalone tree rooted at this node, keeping its descendants, ids and depths as they are,
+    // so ids resolved against the original still work on the extract. The document's preamble is
+    // outside every section and is left behind; its attributes (front matter such as author or
+    // source) still describe where the extract came from, so they carry over.
+    pub fn subtree(&self, node_id: &str) -> Option<DocumentTree> {
+        let node = self.find_node(node_id)?;
+        let mut tree = self.with_root(node.clone());
+        tree.title = node.title.clone();
+        tree.preamble = String::new();
+        Some(tree)
+    }
+
+    // Copy where the node's body is split at lines starting with a paragraph number (`1.`, `2.1`,
+    // `2.1.3.`), each numbered paragraph becoming a child titled by the rest of its first line.
+    // More number parts nest deeper. Text before the first number stays on the node, new children
+    // go ahead of existing subsections, and the tree is renumbered. Unchanged if nothing matches.
+    pub fn split_by_numbered_paragraphs(&self, node_id: &str) -> DocumentTree {
+        let mut root = self.root.clone();
+        let Some(node) = root.find_mut(node_id) else {
+            return self.with_root(root);
+        };
+        let mut intro: Vec<&str> = Vec::new();
+        let mut paragraphs: Vec<(usize, String, Vec<&str>)> = Vec::new();
+        for line in node.text.lines() {
+            match paragraph_number(line) {
+                Some((parts, rest)) => paragraphs.push((parts, rest.to_string(), Vec::new())),
+                None => match paragraphs.last_mut() {
+                    Some((_, _, body)) => body.push(line),
+                    None => intro.push(line),
+                },
+            }
+        }
+        if paragraphs.is_empty() {
+            return self.with_root(root);
+        }
+
+        let min_parts = paragraphs.iter().map(|(parts, _, _)| *parts).min().unwrap_or(1);
+        let mut stack: Vec<Node> = Vec::new();
+        let mut split: Vec<Node> = Vec::new();
+        for (parts, title, body) in &paragraphs {
+            let depth = node.depth + parts - min_parts + 1;
+            let child = Node::new(String::new(), title.clone(), depth, body.join("\n").trim().to_string());
+            while stack.last().is_some_and(|top| top.depth >= depth) {
+                let done = stack.pop().unwrap();
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(done),
+                    None => split.push(done),
+                }
+            }
+            stack.push(child);
+        }
+        while let Some(done) = stack.pop() {
+            match stack.last_mut() {
+                Some(parent) => parent.children.push(done),
+                None => split.push(done),
+            }
+        }
+        node.text = intro.join("\n").trim().to_string();
+        node.children.splice(0..0, split);
+        let mut tree = self.with_root(root);
+        tree.renumber();
+        tree
+    }
+
+    // Removes a node and its subtree, renumbering the rest; false if the id is unknown or the root
+    pub fn delete_node(&mut self, node_id: &str) -> bool {
+        if self.root.detach(node_id).is_none() {
+            return false;
+        }
+        self.renumber();
+        true
+    }
+
+    // Appends a new section as the last child of `parent_id`, one level below it, and returns its
+    // id, numbered by position as renumber would. None for an unknown parent.
+    pub fn add_child(&mut self, parent_id: &str, title: &str, text: &str) -> Option<String> {
+        let parent = self.root.find_mut(parent_id)?;
+        let node_id = positional_id(&parent.node_id, parent.children.len(), &self.id_separator);
+        let child = Node::new(node_id.clone(), title.to_string(), parent.depth + 1, text.to_string());
+        parent.children.push(child);
+        Some(node_id)
+    }
+
+    // Drops nodes with no text and no children, then renumbers so surviving ids have no gaps
+    pub fn prune_empty(&mut self) {
+        self.root.prune_empty_children();
+        self.renumber();
+    }
+
+    // Reattaches a node and its subtree as the last child of `new_parent_id`, see reparent
+    pub fn move_node(&mut self, node_id: &str, new_parent_id: &str) -> bool {
+        self.reparent(node_id, new_parent_id, usize::MAX)
+    }
+
+    pub fn set_embedding(&mut self, node_id: &str, embedding: Vec<f32>) -> bool {
+        match self.root.find_mut(node_id) {
+            Some(node) => {
+                node.set_embedding(embedding);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Replaces a node's own text; children are untouched. False for an unknown id.
+    pub fn update_text(&mut self, node_id: &str, new_text: String) -> bool {
+        match self.root.find_mut(node_id) {
+            Some(node) => {
+                node.text = new_text;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Renames a node in place; ids are positional, so node_id stays the same. The parsed heading
+    // in raw_title no longer describes the node and is dropped.
+    pub fn update_title(&mut self, node_id: &str, new_title: &str) -> bool {
+        match self.root.find_mut(node_id) {
+            Some(node) => {
+                node.title = new_title.to_string();
+                node.raw_title = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Sets one metadata entry on a node. False for an unknown id.
+    pub fn set_metadata(&mut self, node_id: &str, key: &str, value: &str) -> bool {
+        match self.root.find_mut(node_id) {
+            Some(node) => {
+                node.metadata.insert(key.to_string(), value.to_string());
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Records which pages a section covers. False for an unknown id or an end before the start.
+    pub fn set_page_span(&mut self, node_id: &str, start_page: usize, end_page: usize) -> bool {
+        if end_page < start_page {
+            return false;
+        }
+        match self.root.find_mut(node_id) {
+            Some(node) => {
+                node.page_start = Some(start_page);
+                node.page_end = Some(end_page);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Pairs of node ids, in document order, whose own text has a Jaccard similarity above
+    // `threshold`, e.g. boilerplate repeated across sections. Compares every pair once.
+    pub fn find_duplicates(&self, threshold: f32) -> Vec<(String, String)> {
+        let nodes: Vec<(&Node, HashSet<String>)> = self.iter().map(|n| (n, n.word_set())).collect();
+        let mut pairs = Vec::new();
+        for (i, (a, a_words)) in nodes.iter().enumerate() {
+            for (b, b_words) in &nodes[i + 1..] {
+                if jaccard(a_words, b_words) > threshold {
+                    pairs.push((a.node_id.clone(), b.node_id.clone()));
+                }
+            }
+        }
+        pairs
+    }
+
+    // Brute-force cosine similarity against every node that has an embedding of the query's
+    // dimension, best first
+    pub fn nearest(&self, query: &[f32], top_k: usize) -> Vec<(String, f32)> {
+        let mut scored: Vec<(String, f32)> = self
+            .iter()
+            .filter_map(|n| {
+                let embedding = n.embedding.as_deref()?;
+                cosine_similarity(query, embedding).map(|score| (n.node_id.clone(), score))
+            })
+            .collect();
+        scored.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        scored.truncate(top_k);
+        scored
+    }
+
+    // Slug per node id, unique across the document: the second node with a given slug gets `-1`
+    // appended, the third `-2`, and so on, in document order
+    pub fn unique_slugs(&self) -> HashMap<String, String> {
+        let mut used: HashSet<String> = HashSet::new();
+        let mut slugs = HashMap::new();
+        for node in self.iter() {
+            let base = node.slug();
+            let mut slug = base.clone();
+            let mut n = 1;
+            while !used.insert(slug.clone()) {
+                slug = format!("{}-{}", base, n);
+                n += 1;
+            }
+            slugs.insert(node.node_id.clone(), slug);
+        }
+        slugs
+    }
+
+    // Every inline link in the document as (node_id, text, url), in document order
+    pub fn all_links(&self) -> Vec<(String, String, String)> {
+        self.iter()
+            .flat_map(|n| {
+                n.links()
+                    .into_iter()
+                    .map(move |(text, url)| (n.node_id.clone(), text, url))
+            })
+            .collect()
+    }
+
+    // Every inline image in the document as (node_id, alt, src), in document order
+    pub fn all_images(&self) -> Vec<(String, String, String)> {
+        self.iter()
+            .flat_map(|n| {
+                n.images()
+                    .into_iter()
+                    .map(move |(alt, src)| (n.node_id.clone(), alt, src))
+            })
+            .collect()
+    }
+
+    // (source_node_id, target_node_id) for every in-document `[text](#anchor)` link whose anchor
+    // matches a node's unique slug (case-insensitive), in document order. Unresolved anchors and
+    // links to other documents are left out.
+    pub fn section_link_graph(&self) -> Vec<(String, String)> {
+        let by_slug: HashMap<String, String> = self
+            .unique_slugs()
+            .into_iter()
+            .map(|(node_id, slug)| (slug, node_id))
+            .collect();
+        self.all_links()
+            .into_iter()
+            .filter_map(|(source, _, url)| {
+                let anchor = url.strip_prefix('#')?.to_lowercase();
+                by_slug.get(&anchor).map(|target| (source, target.clone()))
+            })
+            .collect()
+    }
+
+    // Bottom-up, gives each parent without a summary the joined summaries of its children, so
+    // summarizing the leaves is enough to summarize the whole tree. Existing summaries are kept,
+    // and a parent none of whose children has a summary stays None.
+    pub fn rollup_summaries(&mut self, joiner: &str) {
+        self.root.rollup_summaries(joiner);
+    }
+
+    pub fn node_hashes(&self) -> HashMap<String, u64> {
+        self.all_nodes()
+            .into_iter()
+            .map(|n| (n.node_id.clone(), n.content_hash()))
+            .collect()
+    }
+
+    // Markdown with each heading at its original level and prefixed by its id, e.g. `## 2.1 Experiment`
+    pub fn to_numbered_markdown(&self) -> String {
+        let mut parts = Vec::new();
+        for node in self.iter() {
+            parts.push(format!("{} {} {}", "#".repeat(node.depth.max(1)), node.node_id, node.title));
+            if !node.text.is_empty() {
+                parts.push(node.text.clone());
+            }
+        }
+        let mut out = parts.join("\n\n");
+        out.push('\n');
+        out
+    }
+
+    // Graphviz digraph with one `[id] title` node per section and parent -> child edges
+    pub fn to_dot(&self) -> String {
+        let mut lines = vec![format!("digraph \"{}\" {{", escape_dot(&self.doc_id))];
+        for node in self.iter() {
+            lines.push(format!(
+                "    \"{}\" [label=\"[{}] {}\"];",
+                escape_dot(&node.node_id),
+                escape_dot(&node.node_id),
+                escape_dot(&node.title)
+            ));
+        }
+        for node in self.iter() {
+            for child in &node.children {
+                lines.push(format!(
+                    "    \"{}\" -> \"{}\";",
+                    escape_dot(&node.node_id),
+                    escape_dot(&child.node_id)
+                ));
+            }
+        }
+        lines.push("}".to_string());
+        lines.join("\n")
+    }
+
+    // Nodes in document order with `parent_id` set to the enclosing node (None for top-level)
+    pub fn flat_nodes(&self) -> Vec<FlatNode> {
+        fn push(node: &Node, parent_id: Option<&str>, out: &mut Vec<FlatNode>) {
+            out.push(FlatNode {
+                node_id: node.node_id.clone(),
+                parent_id: parent_id.map(|p| p.to_string()),
+                title: node.title.clone(),
+                depth: node.depth,
+                text: node.text.clone(),
+                summary: node.summary.clone(),
+            });
+            for child in &node.children {
+                push(child, Some(&node.node_id), out);
+            }
+        }
+        let mut out = Vec::new();
+        for node in self.top_level() {
+            push(node, None, &mut out);
+        }
+        out
+    }
+
+    // One `node_id,parent_id,depth,title,word_count` row per node in document order, after a
+    // header row. Top-level nodes have an empty parent_id; fields are quoted per RFC 4180.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("node_id,parent_id,depth,title,word_count\n");
+        for row in self.flat_nodes() {
+            let fields = [
+                csv_field(&row.node_id),
+                csv_field(row.parent_id.as_deref().unwrap_or("")),
+                row.depth.to_string(),
+                csv_field(&row.title),
+                row.text.split_whitespace().count().to_string(),
+            ];
+            csv.push_str(&fields.join(","));
+            csv.push('\n');
+        }
+        csv
+    }
+
+    // A flat JSON array of nodes, easier to load into a dataframe or table than the nested form
+    pub fn to_flat_json(&self) -> String {
+        self.try_to_flat_json().unwrap_or_default()
+    }
+
+    pub fn try_to_flat_json(&self) -> Result<String, PageIndexError> {
+        to_json_string(&self.flat_nodes())
+    }
+
+    // Rebuilds a tree from to_flat_json output. Ids must be unique and every parent_id must name
+    // another row. doc_id is left empty and the title comes from the first top-level node.
+    pub fn from_flat_json(json: &str) -> Result<DocumentTree, PageIndexError> {
+        let rows: Vec<FlatNode> = serde_json::from_str(json)?;
+        Self::from_flat_nodes(&rows)
+    }
+
+    // Shared by the flat formats: links rows into a tree, checking ids and parent references
+    fn from_flat_nodes(rows: &[FlatNode]) -> Result<DocumentTree, PageIndexError> {
+        let mut children_of: HashMap<Option<&str>, Vec<&FlatNode>> = HashMap::new();
+        let mut seen = HashSet::new();
+        for row in rows {
+            if !seen.insert(row.node_id.as_str()) {
+                return Err(PageIndexError::Parse(format!("duplicate node_id '{}'", row.node_id)));
+            }
+            children_of.entry(row.parent_id.as_deref()).or_default().push(row);
+        }
+        if let Some(orphan) = rows
+            .iter()
+            .find(|r| r.parent_id.as_deref().is_some_and(|p| !seen.contains(p)))
+        {
+            return Err(PageIndexError::Parse(format!(
+                "node '{}' references missing parent",
+                orphan.node_id
+            )));
+        }
+
+        fn build(row: &FlatNode, children_of: &HashMap<Option<&str>, Vec<&FlatNode>>, built: &mut usize) -> Node {
+            *built += 1;
+            let mut node = Node::new(row.node_id.clone(), row.title.clone(), row.depth, row.text.clone());
+            node.summary = row.summary.clone();
+            if let Some(children) = children_of.get(&Some(row.node_id.as_str())) {
+                node.children = children.iter().map(|c| build(c, children_of, built)).collect();
+            }
+            node
+        }
+
+        let mut built = 0;
+        let top: Vec<Node> = children_of
+            .get(&None)
+            .map(|rows| rows.iter().map(|r| build(r, &children_of, &mut built)).collect())
+            .unwrap_or_default();
+        if built != rows.len() {
+            return Err(PageIndexError::Parse("parent_id links form a cycle".to_string()));
+        }
+        let title = top.first().map(|n| n.title.clone()).unwrap_or_default();
+        let root = if top.len() == 1 {
+            top.into_iter().next().unwrap()
+        } else {
+            let mut root = Node::new("0".to_string(), "root".to_string(), 0, String::new());
+            root.children = top;
+            root
+        };
+        Ok(DocumentTree::new(String::new(), title, root))
+    }
+
+    // Nodes without children, in document order; the finest-grained sections for embedding
+    pub fn leaves(&self) -> Vec<&Node> {
+        self.iter().filter(|n| n.children.is_empty()).collect()
+    }
+
+    // Empty on failure; use try_to_json to see why
+    pub fn to_json(&self) -> String {
+        self.try_to_json().unwrap_or_default()
+    }
+
+    pub fn try_to_json(&self) -> Result<String, PageIndexError> {
+        to_json_string(self)
+    }
+
+    // Reads to_json output back, from any schema version up to SCHEMA_VERSION. Older layouts only
+    // lack fields, which take their defaults, so migrating just brings the version up to date.
+    pub fn from_json(json: &str) -> Result<DocumentTree, PageIndexError> {
+        let mut tree: DocumentTree = serde_json::from_str(json)?;
+        if tree.schema_version > SCHEMA_VERSION {
+            return Err(PageIndexError::Parse(format!(
+                "schema_version {} is newer than the supported {}",
+                tree.schema_version, SCHEMA_VERSION
+            )));
+        }
+        tree.schema_version = SCHEMA_VERSION;
+        Ok(tree)
+    }
+
+    // TOML copes badly with deeply nested arrays of tables, so nodes are written as one flat
+    // `[[nodes]]` array in document order, each naming its parent like to_flat_json does
+    #[cfg(feature = "toml")]
+    pub fn to_toml(&self) -> Result<String, PageIndexError> {
+        let document = TomlDocument {
+            doc_id: self.doc_id.clone(),
+            title: self.title.clone(),
+            description: self.description.clone(),
+            attributes: self.attributes.clone(),
+            nodes: self.flat_nodes(),
+        };
+        toml::to_string(&document).map_err(|e| PageIndexError::Serde(e.to_string()))
+    }
+
+    #[cfg(feature = "toml")]
+    pub fn from_toml(input: &str) -> Result<DocumentTree, PageIndexError> {
+        let document: TomlDocument = toml::from_str(input).map_err(|e| PageIndexError::Serde(e.to_string()))?;
+        let mut tree = Self::from_flat_nodes(&document.nodes)?;
+        tree.doc_id = document.doc_id;
+        tree.title = document.title;
+        tree.description = document.description;
+        tree.attributes = document.attributes;
+        Ok(tree)
+    }
+
+    // Same shape as to_json with every node's text, summary, raw title and metadata blanked,
+    // embeddings dropped and no preamble, for clients that only need ids, titles, depths and
+    // nesting. Document-level fields (title, description, attributes) and node spans stay. Empty on
+    // failure; use try_to_json_outline_only to see why
+    pub fn to_json_outline_only(&self) -> String {
+        self.try_to_json_outline_only().unwrap_or_default()
+    }
+
+    pub fn try_to_json_outline_only(&self) -> Result<String, PageIndexError> {
+        let mut root = self.root.clone();
+        root.strip_content();
+        let mut outline = self.with_root(root);
+        outline.preamble.clear();
+        outline.try_to_json()
+    }
+}
+
+#[cfg(feature = "toml")]
+#[derive(Serialize, Deserialize)]
+struct TomlDocument {
+    doc_id: String,
+    title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(default)]
+    attributes: BTreeMap<String, String>,
+    #[serde(default)]
+    nodes: Vec<FlatNode>,
+}
+
+pub struct Nodes<'a> {
+    stack: Vec<&'a Node>,
+}
+
+impl<'a> Iterator for Nodes<'a> {
+    type Item = &'a Node;
+
+    fn next(&mut self) -> Option<&'a Node> {
+        let node = self.stack.pop()?;
+        self.stack.extend(node.children.iter().rev());
+        Some(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_markdown, parse_markdown_with_options, ParseOptions};
+
+    const SAMPLE: &str = r#"
+# Introduction
+Introductory text.
+
+## Background
+Background details.
+
+## Goals
+Goal details.
+
+# Methods
+Method details.
+
+## Experiment
+Experiment details.
+"#;
+
+    fn make_tree() -> DocumentTree {
+        let child1 = Node::new("1.1".to_string(), "Background".to_string(), 2, "Background text.".to_string());
+        let child2 = Node::new("1.2".to_string(), "Goals".to_string(), 2, "Goals text.".to_string());
+        let mut root = Node::new("1".to_string(), "Introduction".to_string(), 1, "Intro text.".to_string());
+        root.children.push(child1);
+        root.children.push(child2);
+        DocumentTree::new("doc1".to_string(), "Introduction".to_string(), root)
+    }
+
+    #[test]
+    fn test_find_root_node() {
+        let tree = make_tree();
+        let node = tree.find_node("1");
+        assert!(node.is_some());
+        assert_eq!(node.unwrap().title, "Introduction");
+    }
+
+    #[test]
+    fn test_find_child_node() {
+        let tree = make_tree();
+        let node = tree.find_node("1.2");
+        assert!(node.is_some());
+        assert_eq!(node.unwrap().title, "Goals");
+    }
+
+    #[test]
+    fn test_top_keywords_rank_repeated_words_first() {
+        let mut node = Node::new("1".to_string(), "Latency".to_string(), 1, String::new());
+        node.text = "The latency of the cache is low. Latency matters, and cache misses raise LATENCY by 10 ms.".to_string();
+        let keywords = node.top_keywords(3);
+        assert_eq!(keywords[0], ("latency".to_string(), 3));
+        assert_eq!(keywords[1], ("cache".to_string(), 2));
+        assert_eq!(keywords.len(), 3);
+        assert!(node.top_keywords(20).iter().all(|(w, _)| w != "the" && w != "10"));
+        assert!(node.top_keywords(0).is_empty());
+    }
+
+    #[test]
+    fn test_jaccard_similarity_and_duplicates() {
+        let mut tree = make_two_section_tree();
+        let notice = "This document is confidential. Do not distribute it outside the company.";
+        tree.root.children[0].children[0].text = notice.to_string();
+        tree.root.children[1].children[0].text = format!("{} Thanks!", notice.to_lowercase());
+        let background = tree.find_node("1.1").unwrap();
+        let experiment = tree.find_node("2.1").unwrap();
+        assert!(background.jaccard_similarity(experiment) > 0.9);
+        assert!(tree.find_node("1").unwrap().jaccard_similarity(tree.find_node("2").unwrap()) < 0.5);
+        assert_eq!(tree.find_duplicates(0.8), vec![("1.1".to_string(), "2.1".to_string())]);
+
+        let empty = Node::new("3".to_string(), "Empty".to_string(), 1, String::new());
+        assert_eq!(empty.jaccard_similarity(&empty), 0.0);
+    }
+
+    #[test]
+    fn test_add_child_appends_numbered_node() {
+        let mut tree = make_tree();
+        assert_eq!(tree.add_child("1", "Scope", "Scope text.").as_deref(), Some("1.3"));
+        let scope = tree.find_node("1.3").unwrap();
+        assert_eq!((scope.title.as_str(), scope.depth, scope.text.as_str()), ("Scope", 2, "Scope text."));
+        assert_eq!(tree.add_child("1.3", "Detail", "").as_deref(), Some("1.3.1"));
+        assert!(tree.add_child("9", "Lost", "").is_none());
+        assert!(tree.verify_id_consistency());
+
+        let mut tree = make_two_section_tree();
+        assert_eq!(tree.add_child("0", "Appendix", "").as_deref(), Some("3"));
+        assert_eq!(tree.find_node("3").unwrap().depth, 1);
+    }
+
+    #[test]
+    fn test_to_csv_quotes_titles() {
+        let mut tree = make_tree();
+        tree.update_title("1.2", "Goals, \"Stretch\" Goals");
+        let csv = tree.to_csv();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "node_id,parent_id,depth,title,word_count");
+        assert_eq!(lines[1], "1,,1,Introduction,2");
+        assert_eq!(lines[3], "1.2,1,2,\"Goals, \"\"Stretch\"\" Goals\",2");
+        assert_eq!(lines.len(), 4);
+    }
+
+    #[test]
+    fn test_contains_checks_membership() {
+        let tree = make_two_section_tree();
+        assert!(tree.contains("2.1"));
+        assert!(!tree.contains("9"));
+    }
+
+    #[test]
+    fn test_find_missing_node_returns_none() {
+        let tree = make_tree();
+        assert!(tree.find_node("9.9").is_none());
+    }
+
+    #[test]
+    fn test_all_node_ids() {
+        let tree = make_tree();
+        let ids = tree.all_node_ids();
+        assert_eq!(ids.len(), 3);
+        assert!(ids.contains(&"1".to_string()));
+        assert!(ids.contains(&"1.1".to_string()));
+        assert!(ids.contains(&"1.2".to_string()));
+    }
+
+    #[test]
+    fn test_all_node_ids_sorted_is_numeric_not_lexicographic() {
+        let mut root = Node::new("1".to_string(), "Chapter".to_string(), 1, String::new());
+        for i in (1..=12).rev() {
+            root.children.push(Node::new(format!("1.{}", i), format!("Part {}", i), 2, String::new()));
+        }
+        let tree = DocumentTree::new("doc".to_string(), "Chapter".to_string(), root);
+        let sorted = tree.all_node_ids_sorted();
+        assert_eq!(sorted.len(), 13);
+        assert_eq!(sorted[0], "1");
+        let pos = |id: &str| sorted.iter().position(|s| s == id).unwrap();
+        assert!(pos("1.9") < pos("1.10"));
+        assert!(pos("1.2") < pos("1.10"));
+        assert_eq!(sorted.last().unwrap(), "1.12");
+    }
+
+    #[test]
+    fn test_flatten_count() {
+        let tree = make_tree();
+        assert_eq!(tree.all_nodes().len(), 3);
+    }
+
+    #[test]
+    fn test_node_count_excludes_synthetic_root() {
+        assert_eq!(make_tree().node_count(), 3);
+        let tree = make_two_section_tree();
+        assert_eq!(tree.node_count(), 4);
+        assert_eq!(tree.root.count(), 5);
+    }
+
+    #[test]
+    fn test_node_count_matches_id_list() {
+        let tree = parse_markdown("doc1", SAMPLE);
+        assert_eq!(tree.node_count(), 5);
+        assert_eq!(tree.node_count(), tree.all_node_ids().len());
+    }
+
+    #[test]
+    fn test_leaves_are_childless_sections() {
+        let tree = parse_markdown("doc1", SAMPLE);
+        let ids: Vec<&str> = tree.leaves().iter().map(|n| n.node_id.as_str()).collect();
+        assert_eq!(ids, vec!["1.1", "1.2", "2.1"]);
+    }
+
+    #[test]
+    fn test_max_depth_excludes_synthetic_root() {
+        assert_eq!(make_tree().max_depth(), 2);
+        let tree = make_two_section_tree();
+        assert_eq!(tree.max_depth(), 2);
+        assert_eq!(tree.root.height(), 3);
+    }
+
+    #[test]
+    fn test_node_count_and_max_depth_for_sample() {
+        let tree = parse_markdown("doc1", SAMPLE);
+        assert_eq!(tree.node_count(), 5);
+        assert_eq!(tree.max_depth(), 2);
+        assert_eq!(parse_markdown("empty", "").max_depth(), 0);
+    }
+
+    #[test]
+    fn test_structure_stats_for_sample() {
+        let stats = parse_markdown("doc1", SAMPLE).structure_stats();
+        assert_eq!(stats.max_depth, 2);
+        assert_eq!(stats.node_count, 5);
+        assert_eq!(stats.leaf_count, 3);
+        assert_eq!(stats.max_fanout, 2);
+        assert!((stats.avg_fanout - 5.0 / 3.0).abs() < 1e-9);
+        assert_eq!(parse_markdown("empty", "").structure_stats().avg_fanout, 0.0);
+    }
+
+    #[test]
+    fn test_inline_code_extracts_single_and_double_backtick_spans() {
+        let text = "Call `foo()` first, then ``bar`baz`` to finish.".to_string();
+        let node = Node::new("1".to_string(), "API".to_string(), 1, text);
+        assert_eq!(node.inline_code(), vec!["foo()", "bar`baz"]);
+    }
+
+    #[test]
+    fn test_inline_code_ignores_escaped_and_unclosed_backticks() {
+        let text = "Literal \\`tick, padded `` `x` `` and an ``unclosed run.".to_string();
+        let node = Node::new("1".to_string(), "API".to_string(), 1, text);
+        assert_eq!(node.inline_code(), vec!["`x`"]);
+    }
+
+    #[test]
+    fn test_links_extracts_inline_links_in_order() {
+        let text = "See [the spec](https://example.com/spec \"Spec\") and [notes](notes.md).";
+        let node = Node::new("1".to_string(), "Refs".to_string(), 1, text.to_string());
+        assert_eq!(
+            node.links(),
+            vec![
+                ("the spec".to_string(), "https://example.com/spec".to_string()),
+                ("notes".to_string(), "notes.md".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_links_skips_images_and_reference_links() {
+        let text = "![logo](logo.png) [ref link][1] [broken](no-close \\[x](y) [ok](<a b.md>)\n\n[1]: https://x";
+        let node = Node::new("1".to_string(), "Refs".to_string(), 1, text.to_string());
+        assert_eq!(node.links(), vec![("ok".to_string(), "a b.md".to_string())]);
+    }
+
+    #[test]
+    fn test_images_extracted_and_links_ignored() {
+        let text = "Intro ![Architecture diagram](img/arch.png \"Arch\") and [x](y).";
+        let node = Node::new("1".to_string(), "Media".to_string(), 1, text.to_string());
+        assert_eq!(node.images(), vec![("Architecture diagram".to_string(), "img/arch.png".to_string())]);
+        assert_eq!(node.links(), vec![("x".to_string(), "y".to_string())]);
+
+        let mut tree = make_tree();
+        tree.root.children[0].text = text.to_string();
+        assert_eq!(
+            tree.all_images(),
+            vec![("1.1".to_string(), "Architecture diagram".to_string(), "img/arch.png".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_all_links_tags_node_ids() {
+        let mut tree = make_tree();
+        tree.root.children[1].text = "Read [this](a.md).".to_string();
+        assert_eq!(
+            tree.all_links(),
+            vec![("1.2".to_string(), "this".to_string(), "a.md".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_content_hash_stable_for_identical_content() {
+        let a = Node::new("1".to_string(), "Goals".to_string(), 1, "Same body.".to_string());
+        let b = Node::new("7".to_string(), "Goals".to_string(), 3, "Same body.".to_string());
+        assert_eq!(a.content_hash(), b.content_hash());
+    }
+
+    #[test]
+    fn test_content_hash_changes_with_body() {
+        let mut tree = make_tree();
+        let before = tree.node_hashes();
+        tree.root.children[1].text = "Revised goals.".to_string();
+        let after = tree.node_hashes();
+        assert_eq!(before.len(), 3);
+        assert_eq!(before["1.1"], after["1.1"]);
+        assert_ne!(before["1.2"], after["1.2"]);
+    }
+
+    fn make_two_section_tree() -> DocumentTree {
+        let mut intro = Node::new("1".to_string(), "Introduction".to_string(), 1, "Intro text.".to_string());
+        intro.children.push(Node::new("1.1".to_string(), "Background".to_string(), 2, "Background text.".to_string()));
+        let mut methods = Node::new("2".to_string(), "Methods".to_string(), 1, "Methods text.".to_string());
+        methods.children.push(Node::new("2.1".to_string(), "Experiment".to_string(), 2, "Experiment text.".to_string()));
+        let mut root = Node::new("0".to_string(), "root".to_string(), 0, String::new());
+        root.children.push(intro);
+        root.children.push(methods);
+        DocumentTree::new("doc2".to_string(), "Introduction".to_string(), root)
+    }
+
+    #[test]
+    fn test_section_link_graph_resolves_anchors_to_nodes() {
+        let mut tree = make_two_section_tree();
+        tree.root.children[0].text = "[see Methods](#methods), [gone](#missing), [web](https://x.org#methods)".to_string();
+        tree.root.children[1].children[0].text = "Builds on [the background](#Background).".to_string();
+        assert_eq!(
+            tree.section_link_graph(),
+            vec![("1".to_string(), "2".to_string()), ("2.1".to_string(), "1.1".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_find_nodes_by_title_returns_every_match() {
+        let mut tree = make_two_section_tree();
+        tree.root.children[0].children[0].title = "Overview".to_string();
+        tree.root.children[1].children[0].title = "overview".to_string();
+        let ids = |nodes: Vec<&Node>| nodes.iter().map(|n| n.node_id.clone()).collect::<Vec<_>>();
+        assert_eq!(ids(tree.find_nodes_by_title("Overview", true)), vec!["1.1", "2.1"]);
+        assert_eq!(ids(tree.find_nodes_by_title("Overview", false)), vec!["1.1"]);
+        assert!(tree.find_nodes_by_title("Summary", true).is_empty());
+    }
+
+    #[test]
+    fn test_enclosing_node_is_lowest_common_ancestor() {
+        let tree = make_two_section_tree();
+        assert_eq!(tree.enclosing_node(&["1.1", "1"]).unwrap().title, "Introduction");
+        assert_eq!(tree.enclosing_node(&["1.1", "2.1"]).unwrap().node_id, "0");
+        assert_eq!(tree.enclosing_node(&["2.1"]).unwrap().title, "Experiment");
+        assert!(tree.enclosing_node(&[]).is_none());
+        assert!(tree.enclosing_node(&["1.1", "9"]).is_none());
+
+        let tree = make_tree();
+        assert_eq!(tree.enclosing_node(&["1.1", "1.2"]).unwrap().title, "Introduction");
+    }
+
+    #[test]
+    fn test_rollup_summaries_joins_child_summaries() {
+        let mut tree = make_two_section_tree();
+        tree.root.children[0].children[0].summary = Some("Prior work.".to_string());
+        tree.root.children[1].summary = Some("Kept as is.".to_string());
+        tree.root.children[1].children[0].summary = Some("One trial.".to_string());
+        tree.rollup_summaries(" ");
+        assert_eq!(tree.find_node("1").unwrap().summary.as_deref(), Some("Prior work."));
+        assert_eq!(tree.find_node("2").unwrap().summary.as_deref(), Some("Kept as is."));
+        assert_eq!(tree.root.summary.as_deref(), Some("Prior work. Kept as is."));
+
+        let mut tree = make_tree();
+        tree.root.children[0].summary = Some("Context.".to_string());
+        tree.root.children[1].summary = Some("Aims.".to_string());
+        tree.rollup_summaries("\n");
+        let summary = tree.root.summary.clone().unwrap();
+        assert!(summary.contains("Context.") && summary.contains("Aims."));
+    }
+
+    #[test]
+    fn test_orphan_text_reports_root_text() {
+        let mut tree = make_two_section_tree();
+        assert!(tree.orphan_text().is_empty());
+        tree.root.text = "Loose text.".to_string();
+        assert_eq!(tree.orphan_text(), vec![("0".to_string(), "Loose text.".to_string())]);
+    }
+
+    #[test]
+    fn test_visible_ids_stop_at_depth() {
+        let tree = make_two_section_tree();
+        assert_eq!(tree.visible_ids(1), vec!["1", "2"]);
+        assert_eq!(tree.visible_ids(2), tree.all_node_ids());
+        assert!(tree.visible_ids(0).is_empty());
+    }
+
+    #[test]
+    fn test_retain_keeps_ancestors_and_prunes_other_sections() {
+        let tree = make_two_section_tree();
+        let kept = tree.retain(&["2.1"]);
+        assert_eq!(kept.all_node_ids(), vec!["2", "2.1"]);
+        assert_eq!(kept.find_node("2").unwrap().text, "Methods text.");
+        assert!(kept.find_node("1").is_none());
+        assert!(kept.find_node("1.1").is_none());
+    }
+
+    #[test]
+    fn test_retain_keeps_full_subtree_of_listed_node() {
+        let tree = make_two_section_tree();
+        let kept = tree.retain(&["1"]);
+        assert_eq!(kept.all_node_ids(), vec!["1", "1.1"]);
+        assert!(tree.retain(&["9.9"]).all_node_ids().is_empty());
+    }
+
+    #[test]
+    fn test_slug_drops_punctuation_and_collapses_hyphens() {
+        let node = Node::new("1".to_string(), "Goals & Objectives!".to_string(), 1, String::new());
+        assert_eq!(node.slug(), "goals-objectives");
+        let node = Node::new("2".to_string(), "  Über   Größe -- Notes ".to_string(), 1, String::new());
+        assert_eq!(node.slug(), "über-größe-notes");
+    }
+
+    #[test]
+    fn test_unique_slugs_suffix_collisions() {
+        let mut tree = make_tree();
+        tree.root.children[1].title = "background".to_string();
+        let slugs = tree.unique_slugs();
+        assert_eq!(slugs["1"], "introduction");
+        assert_eq!(slugs["1.1"], "background");
+        assert_eq!(slugs["1.2"], "background-1");
+    }
+
+    #[test]
+    fn test_word_counts() {
+        let tree = make_tree();
+        assert_eq!(tree.find_node("1.1").unwrap().word_count(), 2);
+        assert_eq!(tree.root.subtree_word_count(), 6);
+    }
+
+    #[test]
+    fn test_reading_time_from_word_count() {
+        let mut tree = make_tree();
+        tree.root.children[0].text = vec!["word"; 400].join(" ");
+        let background = &tree.root.children[0];
+        assert!((background.reading_time_minutes(200) - 2.0).abs() < 1e-6);
+        assert!((tree.root.subtree_reading_time_minutes(100) - 4.04).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_subtree_fractions_are_bounded() {
+        let tree = make_two_section_tree();
+        let intro = tree.subtree_fraction("1").unwrap();
+        let methods = tree.subtree_fraction("2").unwrap();
+        assert!(intro > 0.0 && intro < 1.0);
+        assert!(methods > 0.0 && methods < 1.0);
+        assert!(intro + methods <= 1.0);
+        assert_eq!(tree.subtree_fraction("1.1").unwrap(), 0.25);
+        assert!(tree.subtree_fraction("9").is_none());
+    }
+
+    #[test]
+    fn test_renumber_after_removing_middle_child() {
+        let mut tree = make_tree();
+        tree.root.children.push(Node::new("1.3".to_string(), "Scope".to_string(), 2, "Scope text.".to_string()));
+        tree.root.children.remove(1);
+        tree.renumber();
+        assert_eq!(tree.all_node_ids(), vec!["1", "1.1", "1.2"]);
+        assert_eq!(tree.find_node("1.2").unwrap().title, "Scope");
+    }
+
+    #[test]
+    fn test_merge_keeps_id_separator_and_preambles() {
+        let mut first = make_two_section_tree();
+        first.id_separator = "-".to_string();
+        first.preamble = "First preamble.".to_string();
+        first.renumber();
+        let mut second = make_two_section_tree();
+        second.root.text = "Kept root text.".to_string();
+        let merged = DocumentTree::merge("all", "All", vec![first, make_tree(), second]);
+        assert_eq!(merged.id_separator, "-");
+        assert_eq!(merged.all_node_ids()[..5], ["1", "1-1", "2", "2-1", "3"]);
+        assert_eq!(merged.find_node("3-2").unwrap().title, "Goals");
+        assert_eq!(merged.preamble, "First preamble.\n\nKept root text.");
+        assert!(merged.verify_id_consistency());
+    }
+
+    #[test]
+    fn test_merge_renumbers_later_documents() {
+        let first = parse_markdown("a", SAMPLE);
+        let second = parse_markdown("b", &SAMPLE.replace("Introduction", "Overview"));
+        let merged = DocumentTree::merge("all", "Handbook", vec![first, second]);
+        assert_eq!(merged.title, "Handbook");
+        assert_eq!(merged.root.children.len(), 4);
+        assert_eq!(merged.find_node("3").unwrap().title, "Overview");
+        assert_eq!(merged.find_node("3.2").unwrap().title, "Goals");
+        assert_eq!(merged.find_node("4.1").unwrap().title, "Experiment");
+        assert_eq!(merged.node_count(), 10);
+    }
+
+    #[test]
+    fn test_renumber_closes_skipped_level_gaps() {
+        let mut tree = make_two_section_tree();
+        tree.root.children[1].children[0].node_id = "2.0.1".to_string();
+        tree.renumber();
+        assert_eq!(tree.all_node_ids(), vec!["1", "1.1", "2", "2.1"]);
+    }
+
+    #[test]
+    fn test_ids_stay_consistent_across_insert_and_remove() {
+        let mut tree = make_two_section_tree();
+        assert!(tree.verify_id_consistency());
+        assert!(tree.reparent("2.1", "1", 0));
+        assert_eq!(tree.titles(), vec!["Introduction", "Experiment", "Background", "Methods"]);
+        assert!(tree.verify_id_consistency());
+        assert!(tree.delete_node("1.1"));
+        assert_eq!(tree.all_node_ids(), vec!["1", "1.1", "2"]);
+        assert_eq!(tree.find_node("1.1").unwrap().title, "Background");
+        assert!(tree.verify_id_consistency());
+        tree.root.children[1].node_id = "3".to_string();
+        assert!(!tree.verify_id_consistency());
+    }
+
+    #[test]
+    fn test_split_by_numbered_paragraphs_creates_children() {
+        let mut tree = make_tree();
+        tree.root.children[1].text = "Overall aims.\n1. Ship it\nOn time.\n2. Keep it fast\n2.1 Under 10ms\nIn 2024 terms.".to_string();
+        let split = tree.split_by_numbered_paragraphs("1.2");
+        let goals = split.find_node("1.2").unwrap();
+        assert_eq!(goals.text, "Overall aims.");
+        assert_eq!(goals.children.len(), 2);
+        assert_eq!(split.find_node("1.2.1").unwrap().title, "Ship it");
+        assert_eq!(split.find_node("1.2.1").unwrap().text, "On time.");
+        assert_eq!(split.find_node("1.2.2").unwrap().title, "Keep it fast");
+        let nested = split.find_node("1.2.2.1").unwrap();
+        assert_eq!((nested.title.as_str(), nested.depth), ("Under 10ms", 4));
+        assert_eq!(nested.text, "In 2024 terms.");
+        assert_eq!(tree.split_by_numbered_paragraphs("1.1").to_json(), tree.to_json());
+    }
+
+    #[test]
+    fn test_delete_node_removes_and_renumbers() {
+        let mut tree = make_tree();
+        assert!(tree.delete_node("1.1"));
+        assert_eq!(tree.all_node_ids(), vec!["1", "1.1"]);
+        assert_eq!(tree.find_node("1.1").unwrap().title, "Goals");
+    }
+
+    #[test]
+    fn test_delete_unknown_or_root_returns_false() {
+        let mut tree = make_tree();
+        assert!(!tree.delete_node("9.9"));
+        assert!(!tree.delete_node("1"));
+        assert_eq!(tree.all_node_ids().len(), 3);
+    }
+
+    #[test]
+    fn test_drop_titles_removes_section_and_subtree() {
+        let mut tree = make_two_section_tree();
+        let mut copyright = Node::new("0.1".to_string(), "Copyright".to_string(), 1, "All rights reserved.".to_string());
+        copyright.children.push(Node::new("0.1.1".to_string(), "Licenses".to_string(), 2, "MIT.".to_string()));
+        tree.root.children.insert(0, copyright);
+        tree.renumber();
+
+        let cleaned = tree.drop_titles(&["copyright"], true);
+        assert_eq!(cleaned.all_node_ids(), vec!["1", "1.1", "2", "2.1"]);
+        assert!(cleaned.iter().all(|n| n.title != "Copyright" && n.title != "Licenses"));
+        assert_eq!(cleaned.find_node("1").unwrap().title, "Introduction");
+
+        let untouched = tree.drop_titles(&["copyright"], false);
+        assert_eq!(untouched.all_node_ids().len(), 6);
+    }
+
+    #[test]
+    fn test_drop_titles_removes_matching_promoted_root() {
+        let tree = make_tree();
+        assert_eq!(tree.root.node_id, "1");
+        let cleaned = tree.drop_titles(&["INTRODUCTION"], true);
+        assert_eq!(cleaned.root.node_id, "0");
+        assert!(cleaned.root.children.is_empty());
+        assert!(cleaned.all_node_ids().is_empty());
+        assert_eq!(tree.drop_titles(&["Goals"], false).all_node_ids(), vec!["1", "1.1"]);
+    }
+
+    #[test]
+    fn test_reparent_moves_subtree_and_renumbers() {
+        let mut tree = make_two_section_tree();
+        assert!(tree.reparent("2.1", "1", 0));
+        let intro = tree.find_node("1").unwrap();
+        assert_eq!(intro.children[0].title, "Experiment");
+        assert_eq!(intro.children[0].node_id, "1.1");
+        assert_eq!(intro.children[0].depth, 2);
+        assert_eq!(tree.find_node("1.2").unwrap().title, "Background");
+        assert!(tree.find_node("2").unwrap().children.is_empty());
+    }
+
+    #[test]
+    fn test_move_node_appends_under_new_parent() {
+        let mut tree = make_two_section_tree();
+        assert!(tree.move_node("1.1", "2"));
+        let moved = tree.find_node("2.2").unwrap();
+        assert_eq!(moved.title, "Background");
+        assert_eq!(moved.depth, 2);
+        assert!(tree.find_node("1").unwrap().children.is_empty());
+        assert!(!tree.move_node("2", "2.1"));
+    }
+
+    #[test]
+    fn test_move_node_adjusts_descendant_depths() {
+        let mut tree = make_two_section_tree();
+        assert!(tree.move_node("2", "1.1"));
+        let moved = tree.find_node("1.1.1").unwrap();
+        assert_eq!((moved.title.as_str(), moved.depth), ("Methods", 3));
+        let child = tree.find_node("1.1.1.1").unwrap();
+        assert_eq!((child.title.as_str(), child.depth), ("Experiment", 4));
+    }
+
+    #[test]
+    fn test_reparent_rejects_cycles_and_unknown_ids() {
+        let mut tree = make_two_section_tree();
+        assert!(!tree.reparent("1", "1.1", 0));
+        assert!(!tree.reparent("1", "1", 0));
+        assert!(!tree.reparent("9", "1", 0));
+        assert!(!tree.reparent("1.1", "9", 0));
+        assert_eq!(tree.all_node_ids(), vec!["1", "1.1", "2", "2.1"]);
+    }
+
+    #[test]
+    fn test_prune_empty_removes_contentless_nodes_and_renumbers() {
+        let mut tree = make_two_section_tree();
+        let intro = tree.root.find_mut("1").unwrap();
+        intro.children.insert(0, Node::new("1.0".to_string(), "Stray".to_string(), 2, String::new()));
+        tree.root.children.push(Node::new("3".to_string(), "Appendix".to_string(), 1, String::new()));
+        tree.prune_empty();
+        assert_eq!(tree.all_node_ids(), vec!["1", "1.1", "2", "2.1"]);
+        assert_eq!(tree.find_node("1.1").unwrap().title, "Background");
+        assert!(tree.all_nodes().iter().all(|n| n.title != "Appendix"));
+    }
+
+    #[test]
+    fn test_prune_empty_removes_parent_left_empty() {
+        let mut tree = make_two_section_tree();
+        let methods = tree.root.find_mut("2").unwrap();
+        methods.text.clear();
+        methods.children[0].text.clear();
+        tree.prune_empty();
+        assert_eq!(tree.all_node_ids(), vec!["1", "1.1"]);
+    }
+
+    #[test]
+    fn test_iter_matches_all_nodes_order() {
+        let tree = make_two_section_tree();
+        let lazy: Vec<&str> = tree.iter().map(|n| n.node_id.as_str()).collect();
+        let eager: Vec<&str> = tree.all_nodes().iter().map(|n| n.node_id.as_str()).collect();
+        assert_eq!(lazy, eager);
+    }
+
+    #[test]
+    fn test_page_span_round_trips_through_json() {
+        let mut tree = make_tree();
+        assert!(tree.set_page_span("1.2", 12, 14));
+        assert!(!tree.set_page_span("9", 1, 2));
+        assert!(!tree.set_page_span("1.1", 5, 4));
+        let restored = DocumentTree::from_json(&tree.to_json()).unwrap();
+        let goals = restored.find_node("1.2").unwrap();
+        assert_eq!((goals.page_start, goals.page_end), (Some(12), Some(14)));
+        assert_eq!(restored.find_node("1.1").unwrap().page_start, None);
+        assert!(!make_tree().to_json().contains("page_start"));
+        assert!(matches!(DocumentTree::from_json("{"), Err(PageIndexError::Serde(_))));
+    }
+
+    #[test]
+    fn test_from_json_migrates_v1_and_rejects_newer_versions() {
+        let v1 = r#"{"doc_id": "old", "title": "Intro", "description": null, "root": {"node_id": "1",
+            "title": "Intro", "depth": 1, "text": "Hi.", "summary": null, "children": []}}"#;
+        let tree = DocumentTree::from_json(v1).unwrap();
+        assert_eq!(tree.schema_version, SCHEMA_VERSION);
+        assert_eq!(tree.id_separator, ".");
+        assert!(tree.attributes.is_empty());
+        assert_eq!((tree.root.start_line, tree.root.byte_end, tree.root.page_start), (0, 0, None));
+        assert!(tree.to_json().contains(&format!("\"schema_version\": {}", SCHEMA_VERSION)));
+
+        let future = tree.to_json().replace(&format!("\"schema_version\": {}", SCHEMA_VERSION), "\"schema_version\": 99");
+        assert!(matches!(DocumentTree::from_json(&future), Err(PageIndexError::Parse(_))));
+    }
+
+    #[test]
+    fn test_node_metadata_round_trips_through_json() {
+        let mut tree = make_tree();
+        assert!(tree.set_metadata("1.1", "source", "https://example.org/bg"));
+        assert!(tree.set_metadata("1.1", "tags", "context,history"));
+        assert!(!tree.set_metadata("9", "source", "x"));
+        let restored = DocumentTree::from_json(&tree.to_json()).unwrap();
+        let metadata = &restored.find_node("1.1").unwrap().metadata;
+        assert_eq!(metadata.get("source").map(String::as_str), Some("https://example.org/bg"));
+        assert_eq!(metadata.len(), 2);
+        assert!(restored.find_node("1.2").unwrap().metadata.is_empty());
+        assert!(!make_tree().to_json().contains("metadata"));
+    }
+
+    #[test]
+    fn test_walk_visits_nodes_with_ancestors() {
+        let tree = make_two_section_tree();
+        let mut leaf_ids = HashSet::new();
+        let mut paths = Vec::new();
+        tree.walk(|node, ancestors| {
+            if node.children.is_empty() {
+                leaf_ids.insert(node.node_id.clone());
+            }
+            let titles: Vec<&str> = ancestors.iter().map(|a| a.title.as_str()).collect();
+            paths.push((node.node_id.clone(), titles.join("/")));
+        });
+        let expected: HashSet<String> = tree.leaves().iter().map(|n| n.node_id.clone()).collect();
+        assert_eq!(leaf_ids, expected);
+        assert_eq!(paths[0], ("1".to_string(), String::new()));
+        assert_eq!(paths[3], ("2.1".to_string(), "Methods".to_string()));
+    }
+
+    #[test]
+    fn test_bfs_nodes_go_level_by_level() {
+        let tree = parse_markdown("doc1", SAMPLE);
+        let ids: Vec<&str> = tree.bfs_nodes().iter().map(|n| n.node_id.as_str()).collect();
+        assert_eq!(ids, vec!["1", "2", "1.1", "1.2", "2.1"]);
+        assert!(parse_markdown("empty", "").bfs_nodes().is_empty());
+    }
+
+    #[test]
+    fn test_attributes_round_trip_through_json() {
+        let mut tree = make_tree();
+        tree.set_attribute("author", "Ada");
+        tree.set_attribute("source", "https://example.com/report");
+        let restored: DocumentTree = serde_json::from_str(&tree.to_json()).unwrap();
+        assert_eq!(restored.attribute("author"), Some("Ada"));
+        assert_eq!(restored.attribute("source"), Some("https://example.com/report"));
+        assert_eq!(restored.attributes.len(), 2);
+    }
+
+    #[test]
+    fn test_set_attribute_replaces_and_remove_clears() {
+        let mut tree = make_tree();
+        assert_eq!(tree.set_attribute("author", "Ada"), None);
+        assert_eq!(tree.set_attribute("author", "Grace"), Some("Ada".to_string()));
+        assert_eq!(tree.remove_attribute("author"), Some("Grace".to_string()));
+        assert!(tree.attribute("author").is_none());
+    }
+
+    fn make_mislabeled_tree() -> DocumentTree {
+        let mut chapter = Node::new("intro".to_string(), "Chapter".to_string(), 1, "Chapter text.".to_string());
+        let mut section = Node::new("7".to_string(), "Section".to_string(), 2, "Section text.".to_string());
+        section.children.push(Node::new("7.1".to_string(), "Detail".to_string(), 3, "Detail text.".to_string()));
+        chapter.children.push(section);
+        DocumentTree::new("custom".to_string(), "Chapter".to_string(), chapter)
+    }
+
+    #[test]
+    fn test_node_path_walks_tree() {
+        let tree = make_two_section_tree();
+        let titles: Vec<&str> = tree.node_path("2.1").unwrap().iter().map(|n| n.title.as_str()).collect();
+        assert_eq!(titles, vec!["Methods", "Experiment"]);
+        assert!(tree.node_path("9").is_none());
+    }
+
+    #[test]
+    fn test_verify_breadcrumbs() {
+        assert!(make_two_section_tree().verify_breadcrumbs());
+        assert!(make_tree().verify_breadcrumbs());
+        assert!(!make_mislabeled_tree().verify_breadcrumbs());
+    }
+
+    #[test]
+    fn test_to_dot_declares_nodes_and_edges() {
+        let dot = make_tree().to_dot();
+        assert!(dot.starts_with("digraph \"doc1\" {"));
+        assert!(dot.contains("\"1.1\" [label=\"[1.1] Background\"];"));
+        assert!(dot.contains("\"1\" -> \"1.1\";"));
+        assert!(dot.ends_with('}'));
+    }
+
+    #[test]
+    fn test_to_dot_escapes_quotes_in_titles() {
+        let mut tree = make_tree();
+        tree.root.children[0].title = "The \"Why\"".to_string();
+        assert!(tree.to_dot().contains("[label=\"[1.1] The \\\"Why\\\"\"]"));
+    }
+
+    #[test]
+    fn test_to_flat_json_lists_every_node_with_parent() {
+        let tree = make_two_section_tree();
+        let rows: Vec<serde_json::Value> = serde_json::from_str(&tree.to_flat_json()).unwrap();
+        assert_eq!(rows.len(), tree.all_node_ids().len());
+        let background = rows.iter().find(|r| r["node_id"] == "1.1").unwrap();
+        assert_eq!(background["parent_id"], "1");
+        assert_eq!(background["text"], "Background text.");
+        let intro = rows.iter().find(|r| r["node_id"] == "1").unwrap();
+        assert!(intro["parent_id"].is_null());
+        assert!(intro.get("children").is_none());
+    }
+
+    #[test]
+    fn test_from_flat_json_round_trips_structure() {
+        let tree = make_two_section_tree();
+        let restored = DocumentTree::from_flat_json(&tree.to_flat_json()).unwrap();
+        assert_eq!(restored.title, "Introduction");
+        assert_eq!(restored.flat_nodes(), tree.flat_nodes());
+        assert_eq!(restored.find_node("2").unwrap().children[0].title, "Experiment");
+
+        let single = DocumentTree::from_flat_json(&make_tree().to_flat_json()).unwrap();
+        assert_eq!(single.root.node_id, "1");
+        assert_eq!(single.root.children.len(), 2);
+    }
+
+    #[test]
+    fn test_serialization_failure_is_an_error() {
+        struct Failing;
+        impl Serialize for Failing {
+            fn serialize<S: serde::Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+                Err(serde::ser::Error::custom("forced failure"))
+            }
+        }
+        let err = to_json_string(&Failing).unwrap_err();
+        assert!(matches!(err, PageIndexError::Serde(ref msg) if msg.contains("forced failure")));
+        assert!(make_tree().try_to_json().unwrap().contains("\"Introduction\""));
+        assert!(matches!(make_tree().require_node("9"), Err(PageIndexError::NotFound(id)) if id == "9"));
+    }
+
+    #[test]
+    fn test_from_flat_json_rejects_bad_links() {
+        let duplicate = r#"[{"node_id":"1","parent_id":null,"title":"A","depth":1,"text":"","summary":null},
+                            {"node_id":"1","parent_id":null,"title":"B","depth":1,"text":"","summary":null}]"#;
+        assert!(DocumentTree::from_flat_json(duplicate).unwrap_err().to_string().contains("duplicate"));
+        let orphan = r#"[{"node_id":"1.1","parent_id":"1","title":"A","depth":2,"text":"","summary":null}]"#;
+        assert!(DocumentTree::from_flat_json(orphan).unwrap_err().to_string().contains("missing parent"));
+        let cycle = r#"[{"node_id":"a","parent_id":"b","title":"A","depth":1,"text":"","summary":null},
+                        {"node_id":"b","parent_id":"a","title":"B","depth":1,"text":"","summary":null}]"#;
+        assert!(DocumentTree::from_flat_json(cycle).unwrap_err().to_string().contains("cycle"));
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn test_toml_round_trip_preserves_structure() {
+        let mut tree = make_two_section_tree();
+        tree.set_attribute("author", "Ada");
+        let toml = tree.to_toml().unwrap();
+        assert!(toml.contains("[[nodes]]"));
+        let back = DocumentTree::from_toml(&toml).unwrap();
+        assert_eq!(back.doc_id, "doc2");
+        assert_eq!(back.attribute("author"), Some("Ada"));
+        assert_eq!(back.flat_nodes(), tree.flat_nodes());
+    }
+
+    #[test]
+    fn test_nearest_ranks_closer_embedding_first() {
+        let mut tree = make_tree();
+        assert!(tree.set_embedding("1.1", vec![1.0, 0.0, 0.0]));
+        assert!(tree.set_embedding("1.2", vec![0.6, 0.8, 0.0]));
+        assert!(!tree.set_embedding("9", vec![1.0]));
+        let ranked = tree.nearest(&[0.5, 0.9, 0.0], 5);
+        assert_eq!(ranked.len(), 2);
+        assert_eq!(ranked[0].0, "1.2");
+        assert_eq!(ranked[1].0, "1.1");
+        assert!(ranked[0].1 > ranked[1].1);
+        assert_eq!(tree.nearest(&[0.5, 0.9, 0.0], 1).len(), 1);
+    }
+
+    #[test]
+    fn test_embedding_persists_in_json() {
+        let mut tree = make_tree();
+        tree.set_embedding("1.1", vec![0.25, -1.5]);
+        assert!(!make_tree().to_json().contains("embedding"));
+        let restored: DocumentTree = serde_json::from_str(&tree.to_json()).unwrap();
+        assert_eq!(restored.find_node("1.1").unwrap().embedding, Some(vec![0.25, -1.5]));
+    }
+
+    #[test]
+    fn test_subtree_rebases_on_node() {
+        let tree = make_two_section_tree();
+        let chapter = tree.subtree("1").unwrap();
+        assert_eq!(chapter.title, "Introduction");
+        assert_eq!(chapter.doc_id, "doc2");
+        assert_eq!(chapter.root.node_id, "1");
+        assert_eq!(chapter.all_node_ids(), vec!["1", "1.1"]);
+        assert!(chapter.find_node("2").is_none());
+        assert!(tree.subtree("9").is_none());
+    }
+
+    #[test]
+    fn test_subtree_of_introduction() {
+        let tree = parse_markdown("doc1", SAMPLE);
+        let intro = tree.subtree("1").unwrap();
+        assert_eq!(intro.title, "Introduction");
+        let titles: Vec<&str> = intro.root.children.iter().map(|c| c.title.as_str()).collect();
+        assert_eq!(titles, vec!["Background", "Goals"]);
+    }
+
+    #[test]
+    fn test_to_json_contains_title() {
+        let tree = make_tree();
+        let json = tree.to_json();
+        assert!(json.contains("Introduction"));
+        assert!(json.contains("1.1"));
+    }
+
+    #[test]
+    fn test_to_json_outline_only_omits_bodies() {
+        let mut tree = make_tree();
+        tree.root.children[0].summary = Some("Short summary.".to_string());
+        let json = tree.to_json_outline_only();
+        assert!(!json.contains("Background text."));
+        assert!(!json.contains("Short summary."));
+        assert!(json.contains("\"1.1\""));
+        assert!(json.contains("Background"));
+        assert!(tree.to_json().contains("Background text."));
+        assert_eq!(tree.try_to_json_outline_only().unwrap(), json);
+    }
+
+    #[test]
+    fn test_subtree_leaves_preamble_and_keeps_attributes() {
+        let md = "---\nauthor: Ada\n---\nBefore any heading.\n# Guide\nBody.\n# Notes\nMore.";
+        let options = ParseOptions::new().front_matter(true);
+        let tree = parse_markdown_with_options("doc1", md, &options);
+        assert_eq!(tree.preamble, "Before any heading.");
+        let chapter = tree.subtree("1").unwrap();
+        assert_eq!(chapter.preamble, "");
+        assert!(chapter.orphan_text().is_empty());
+        assert!(!chapter.to_json().contains("Before any heading."));
+        assert_eq!(chapter.attribute("author"), Some("Ada"));
+    }
+
+    #[test]
+    fn test_to_json_outline_only_omits_preamble_and_metadata() {
+        let mut tree = parse_markdown("doc1", "Before any heading.\n# Guide\nBody.\n# Notes\nMore.");
+        assert_eq!(tree.preamble, "Before any heading.");
+        tree.set_metadata("1", "owner", "secret-owner");
+        let json = tree.to_json_outline_only();
+        assert!(!json.contains("Before any heading."));
+        assert!(!json.contains("secret-owner"));
+        assert!(json.contains("Guide"));
+        assert!(tree.to_json().contains("Before any heading."));
     }
 }